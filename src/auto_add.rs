@@ -0,0 +1,197 @@
+/*! The rules for which files [crate::CodexRepository::commit] stages. */
+use crate::{CodexRepository, Result};
+use ansi_term::Colour::*;
+use getset::{CopyGetters, Getters, Setters};
+use git2::{Pathspec, PathspecFlags, Status, StatusOptions};
+use log::trace;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// tracing macro
+macro_rules! auto_add_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Blue).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `AutoAdd` is the rules for which new and changed files [CodexRepository::commit] stages. The patterns are globs relative to the top of the working tree, as git pathspecs (such as `*.csv`, or `.` for everything). In the configuration it can be a list of include patterns, as in older configurations, or a structure. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters, Setters, Serialize, Deserialize)]
+#[serde(from = "AutoAddConfig")]
+pub struct AutoAdd {
+    /// stage files matching any of these
+    #[getset(get = "pub", set = "pub")]
+    include: Vec<String>,
+    /// but not files matching any of these
+    #[getset(get = "pub", set = "pub")]
+    exclude: Vec<String>,
+    /// and not files larger than this (in bytes)
+    #[getset(get_copy = "pub", set = "pub")]
+    max_file_size: Option<u64>,
+    /// and not files ignored by `.gitignore`
+    #[getset(get_copy = "pub", set = "pub")]
+    respect_gitignore: bool,
+    /// have [CodexRepository::commit] look for changed files itself, so that it commits them even if [CodexRepository::add] was not called
+    #[getset(get_copy = "pub", set = "pub")]
+    detect: bool,
+}
+impl Default for AutoAdd {
+    fn default() -> Self {
+        Self {
+            include: vec![],
+            exclude: vec![],
+            max_file_size: None,
+            respect_gitignore: true,
+            detect: false,
+        }
+    }
+}
+impl AutoAdd {
+    /** `new` makes the rules to stage the files matching the patterns (except those ignored by `.gitignore`) */
+    pub fn new(include: Vec<String>) -> Self {
+        Self {
+            include,
+            ..Default::default()
+        }
+    }
+}
+impl From<Vec<String>> for AutoAdd {
+    fn from(include: Vec<String>) -> Self {
+        Self::new(include)
+    }
+}
+
+/** `AutoAddConfig` is how [AutoAdd] can be written in the configuration */
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AutoAddConfig {
+    Patterns(Vec<String>),
+    Rules {
+        #[serde(default)]
+        include: Vec<String>,
+        #[serde(default)]
+        exclude: Vec<String>,
+        #[serde(default)]
+        max_file_size: Option<u64>,
+        #[serde(default = "yes")]
+        respect_gitignore: bool,
+        #[serde(default)]
+        detect: bool,
+    },
+}
+fn yes() -> bool {
+    true
+}
+impl From<AutoAddConfig> for AutoAdd {
+    fn from(config: AutoAddConfig) -> Self {
+        match config {
+            AutoAddConfig::Patterns(include) => Self::new(include),
+            AutoAddConfig::Rules {
+                include,
+                exclude,
+                max_file_size,
+                respect_gitignore,
+                detect,
+            } => Self {
+                include,
+                exclude,
+                max_file_size,
+                respect_gitignore,
+                detect,
+            },
+        }
+    }
+}
+
+/** A `Matcher` checks paths against the include and exclude patterns of an [AutoAdd] */
+pub(crate) struct Matcher {
+    include: Pathspec,
+    exclude: Option<Pathspec>,
+}
+impl Matcher {
+    pub(crate) fn new(rules: &AutoAdd) -> Result<Self> {
+        Ok(Self {
+            include: Pathspec::new(rules.include.iter())?,
+            exclude: if rules.exclude.is_empty() {
+                None
+            } else {
+                Some(Pathspec::new(rules.exclude.iter())?)
+            },
+        })
+    }
+    /** `matches` is whether a path (relative to the top of the working tree) is included and not excluded */
+    pub(crate) fn matches(&self, path: &Path) -> bool {
+        self.include.matches_path(path, PathspecFlags::DEFAULT)
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|e| e.matches_path(path, PathspecFlags::DEFAULT))
+    }
+}
+
+impl CodexRepository {
+    /** `auto_add_dry_run` lists the files (relative to the top of the working tree) that the next [Self::commit] would stage under the [AutoAdd] rules, without staging them: new and changed files, and tracked files that were deleted (or renamed away), whose deletion is staged. Files already staged by [Self::add] are committed as well. */
+    pub fn auto_add_dry_run(&self) -> Result<Vec<String>> {
+        let rules = &self.config.auto_add;
+        if rules.include.is_empty() {
+            return Ok(vec![]);
+        }
+        let matcher = Matcher::new(rules)?;
+        let workdir = self.repo.workdir().unwrap_or_else(|| Path::new("."));
+        let mut options = StatusOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_ignored(!rules.respect_gitignore)
+            .recurse_ignored_dirs(!rules.respect_gitignore);
+        let changed = Status::WT_NEW
+            | Status::WT_MODIFIED
+            | Status::WT_TYPECHANGE
+            | Status::WT_DELETED
+            | Status::WT_RENAMED;
+        let mut found = vec![];
+        for entry in self.repo.statuses(Some(&mut options))?.iter() {
+            let status = entry.status();
+            if !status.intersects(changed) && !status.is_ignored() {
+                continue;
+            }
+            // a rename deletes the old path as well as adding the new one
+            let mut paths = vec![];
+            if let Some(diff) = entry.index_to_workdir() {
+                if status.is_wt_renamed() {
+                    paths.extend(diff.old_file().path().map(|p| p.to_path_buf()));
+                }
+                paths.extend(diff.new_file().path().map(|p| p.to_path_buf()));
+            } else if let Some(p) = entry.path() {
+                paths.push(p.into());
+            }
+            for path in paths {
+                if !matcher.matches(&path) {
+                    continue;
+                }
+                if let (Some(max), Ok(meta)) = (
+                    rules.max_file_size,
+                    std::fs::symlink_metadata(workdir.join(&path)),
+                ) {
+                    if meta.len() > max {
+                        auto_add_trace!(
+                            "not adding {:?}, {} bytes is too large",
+                            &path,
+                            meta.len()
+                        );
+                        continue;
+                    }
+                }
+                let path = path.to_string_lossy().to_string();
+                if !found.contains(&path) {
+                    found.push(path);
+                }
+            }
+        }
+        auto_add_trace!("auto add would stage {:?}", &found);
+        Ok(found)
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
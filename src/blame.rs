@@ -0,0 +1,99 @@
+/*! Who last changed each line of a file. */
+use crate::history::to_datetime;
+use crate::{CodexRepository, Result, User};
+use ansi_term::Colour::*;
+use chrono::{DateTime, FixedOffset};
+use getset::{CopyGetters, Getters};
+use git2::{BlameOptions, Oid};
+use log::trace;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// tracing macro
+macro_rules! blame_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Yellow).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `BlameRange` is a run of lines last changed by the same commit. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct BlameRange {
+    /// first line (counting from 1) in the file at the blamed revision
+    #[getset(get_copy = "pub")]
+    start_line: usize,
+    /// number of lines
+    #[getset(get_copy = "pub")]
+    lines: usize,
+    /// the commit that last changed the lines
+    #[getset(get_copy = "pub")]
+    oid: Oid,
+    /// the author of that commit, as written by [CodexRepository::commit] from the config's [User]
+    #[getset(get = "pub")]
+    author: User,
+    /// when that commit was made
+    #[getset(get_copy = "pub")]
+    time: DateTime<FixedOffset>,
+    /// path of the file in that commit, if it has since been renamed
+    #[getset(get = "pub")]
+    original_path: Option<String>,
+    /// first line of the range in that commit
+    #[getset(get_copy = "pub")]
+    original_start_line: usize,
+}
+impl BlameRange {
+    /** `line_range` is the lines (counting from 1) covered by the range */
+    pub fn line_range(&self) -> RangeInclusive<usize> {
+        self.start_line..=self.start_line + self.lines - 1
+    }
+}
+
+impl CodexRepository {
+    /** `blame` finds who last changed each line of a file (relative to the top of the working tree) at a revision, such as `HEAD`. */
+    pub fn blame(&self, path: &Path, rev: &str) -> Result<Vec<BlameRange>> {
+        self.blame_lines(path, rev, None)
+    }
+    /** `blame_lines` is [Self::blame] for only some lines (counting from 1) of the file. */
+    pub fn blame_lines(
+        &self,
+        path: &Path,
+        rev: &str,
+        lines: Option<RangeInclusive<usize>>,
+    ) -> Result<Vec<BlameRange>> {
+        blame_trace!("blame {:?} at {} lines {:?}", path, rev, &lines);
+        let newest = self.repo.revparse_single(rev)?.peel_to_commit()?.id();
+        let mut options = BlameOptions::new();
+        options.newest_commit(newest);
+        if let Some(lines) = &lines {
+            options
+                .min_line((*lines.start()).max(1))
+                .max_line(*lines.end());
+        }
+        let blame = self.repo.blame_file(path, Some(&mut options))?;
+        let mut ranges = vec![];
+        for hunk in blame.iter() {
+            let signature = hunk.final_signature();
+            let original_path = hunk
+                .path()
+                .filter(|p| *p != path)
+                .map(|p| p.to_string_lossy().replace('\\', "/"));
+            ranges.push(BlameRange {
+                start_line: hunk.final_start_line(),
+                lines: hunk.lines_in_hunk(),
+                oid: hunk.final_commit_id(),
+                author: User::new(
+                    signature.name().unwrap_or(""),
+                    signature.email().unwrap_or(""),
+                ),
+                time: to_datetime(signature.when())?,
+                original_path,
+                original_start_line: hunk.orig_start_line(),
+            });
+        }
+        Ok(ranges)
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,217 @@
+/*! Branches: listing, creating, switching, deleting and tracking. */
+use crate::delete::unpushed_commits;
+use crate::{CodexGitError, CodexRepository, NullResult, Result};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use getset::{CopyGetters, Getters};
+use git2::build::CheckoutBuilder;
+use git2::{Branch, BranchType, Oid, StatusOptions};
+use log::trace;
+
+/// tracing macro
+macro_rules! branch_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Blue).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `REMOTE` is the name of the remote, as set up by [crate::CodexRepoConfig::clone_repo]. */
+pub(crate) const REMOTE: &str = "origin";
+
+/** A `BranchInfo` describes a local or remote-tracking branch. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct BranchInfo {
+    /// short name, such as `main` or (for a remote branch) `origin/main`
+    #[getset(get = "pub")]
+    name: String,
+    /// whether this is a remote-tracking branch
+    #[getset(get_copy = "pub")]
+    remote: bool,
+    /// whether this is the current branch
+    #[getset(get_copy = "pub")]
+    current: bool,
+    /// the commit at the tip of the branch
+    #[getset(get_copy = "pub")]
+    oid: Option<Oid>,
+    /// the remote branch this branch tracks, such as `origin/main`
+    #[getset(get = "pub")]
+    upstream: Option<String>,
+    /// commits on this branch that are not on the upstream
+    #[getset(get_copy = "pub")]
+    ahead: usize,
+    /// commits on the upstream that are not on this branch
+    #[getset(get_copy = "pub")]
+    behind: usize,
+}
+
+impl CodexRepository {
+    /** `current_branch` is the name of the branch that is checked out, failing with [CodexGitError::DetachedHead] if no branch is */
+    pub fn current_branch(&self) -> Result<String> {
+        self.check_on_branch()?;
+        let head = self.repo.find_reference("HEAD")?;
+        let target = head
+            .symbolic_target()
+            .ok_or_else(|| anyhow!("HEAD is not on a branch"))?;
+        Ok(target
+            .strip_prefix("refs/heads/")
+            .ok_or_else(|| anyhow!("HEAD is not on a branch: {}", target))?
+            .to_string())
+    }
+    /** `check_on_branch` fails with [CodexGitError::DetachedHead] if `HEAD` is not on a branch */
+    pub(crate) fn check_on_branch(&self) -> NullResult {
+        if self.repo.head_detached()? {
+            let head = self
+                .repo
+                .head()?
+                .target()
+                .ok_or_else(|| anyhow!("HEAD has no commit"))?;
+            return Err(CodexGitError::DetachedHead(head.to_string()));
+        }
+        Ok(())
+    }
+    /** `branches` lists the local branches and then the remote-tracking branches */
+    pub fn branches(&self) -> Result<Vec<BranchInfo>> {
+        let current = self.current_branch().ok();
+        let mut found = vec![];
+        for kind in [BranchType::Local, BranchType::Remote] {
+            for branch in self.repo.branches(Some(kind))? {
+                let (branch, _) = branch?;
+                let name = branch_name(&branch)?;
+                if kind == BranchType::Remote && name.ends_with("/HEAD") {
+                    continue;
+                }
+                let oid = branch.get().target();
+                let upstream = branch.upstream().ok();
+                let (ahead, behind) = match (oid, upstream.as_ref().and_then(|u| u.get().target()))
+                {
+                    (Some(local), Some(remote)) => self.repo.graph_ahead_behind(local, remote)?,
+                    _ => (0, 0),
+                };
+                found.push(BranchInfo {
+                    current: kind == BranchType::Local && current.as_deref() == Some(&name),
+                    name,
+                    remote: kind == BranchType::Remote,
+                    oid,
+                    upstream: match upstream {
+                        Some(u) => Some(branch_name(&u)?),
+                        None => None,
+                    },
+                    ahead,
+                    behind,
+                });
+            }
+        }
+        Ok(found)
+    }
+    /** `create_branch` makes a local branch at a revision (such as `HEAD` or `origin/main`), without switching to it */
+    pub fn create_branch(&mut self, name: &str, rev: &str) -> NullResult {
+        branch_trace!("creating branch {} at {}", name, rev);
+        let commit = self.repo.revparse_single(rev)?.peel_to_commit()?;
+        self.repo.branch(name, &commit, false)?;
+        Ok(())
+    }
+    /** `switch_branch` checks out a local branch, or makes a local branch tracking the remote branch of that name. It fails with [CodexGitError::DirtyWorkingTree] if tracked files have changes that are not committed. */
+    pub fn switch_branch(&mut self, name: &str) -> NullResult {
+        branch_trace!("switching to branch {}", name);
+        let changed = self.tracked_changes()?;
+        if !changed.is_empty() {
+            return Err(CodexGitError::DirtyWorkingTree { paths: changed });
+        }
+        let branch = match self.repo.find_branch(name, BranchType::Local) {
+            Ok(b) => b,
+            Err(_) => {
+                let remote = format!("{}/{}", REMOTE, name);
+                let commit = self
+                    .repo
+                    .find_branch(&remote, BranchType::Remote)?
+                    .get()
+                    .peel_to_commit()?;
+                let mut branch = self.repo.branch(name, &commit, false)?;
+                branch.set_upstream(Some(&remote))?;
+                branch
+            }
+        };
+        let refname = branch
+            .get()
+            .name()
+            .ok_or_else(|| anyhow!("bad branch name"))?
+            .to_string();
+        let tree = branch.get().peel_to_tree()?;
+        self.repo
+            .checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        self.repo.set_head(&refname)?;
+        // only commits already on a remote branch are pushed on drop
+        self.needs_push = branch.upstream().is_ok() && unpushed_commits(&self.repo)? > 0;
+        Ok(())
+    }
+    /** `delete_branch` deletes a local branch. Unless `force` is set, it fails with [CodexGitError::UnmergedBranch] if the branch has commits that are not on the current branch or on its own upstream. The current branch cannot be deleted. */
+    pub fn delete_branch(&mut self, name: &str, force: bool) -> NullResult {
+        branch_trace!("deleting branch {} (force {})", name, force);
+        if self.current_branch().ok().as_deref() == Some(name) {
+            return Err(CodexGitError::Other(anyhow!(
+                "cannot delete the current branch {}",
+                name
+            )));
+        }
+        let mut branch = self.repo.find_branch(name, BranchType::Local)?;
+        if !force {
+            let tip = branch
+                .get()
+                .target()
+                .ok_or_else(|| anyhow!("branch {} has no commit", name))?;
+            let merged_into = |other: Option<Oid>| -> Result<bool> {
+                Ok(match other {
+                    Some(other) => other == tip || self.repo.graph_descendant_of(other, tip)?,
+                    None => false,
+                })
+            };
+            let head = self.repo.head().ok().and_then(|h| h.target());
+            let upstream = branch.upstream().ok().and_then(|u| u.get().target());
+            if !merged_into(head)? && !merged_into(upstream)? {
+                return Err(CodexGitError::UnmergedBranch(name.to_string()));
+            }
+        }
+        branch.delete()?;
+        Ok(())
+    }
+    /** `set_upstream` sets (or, with `None`, removes) the remote branch, such as `origin/main`, that a local branch tracks */
+    pub fn set_upstream(&mut self, name: &str, upstream: Option<&str>) -> NullResult {
+        branch_trace!("upstream of {} is {:?}", name, upstream);
+        self.repo
+            .find_branch(name, BranchType::Local)?
+            .set_upstream(upstream)?;
+        Ok(())
+    }
+    /** `upstream_branch` is the name on the remote of the branch tracked by a local branch (such as `main`), if any */
+    pub(crate) fn upstream_branch(&self, name: &str) -> Option<String> {
+        let merge = self
+            .repo
+            .config()
+            .ok()?
+            .get_string(&format!("branch.{}.merge", name))
+            .ok()?;
+        merge.strip_prefix("refs/heads/").map(|s| s.to_string())
+    }
+    /** `tracked_changes` lists the tracked files with changes in the index or working tree */
+    pub(crate) fn tracked_changes(&self) -> Result<Vec<String>> {
+        let mut options = StatusOptions::new();
+        options.include_untracked(false).include_ignored(false);
+        Ok(self
+            .repo
+            .statuses(Some(&mut options))?
+            .iter()
+            .filter_map(|entry| entry.path().map(|p| p.to_string()))
+            .collect())
+    }
+}
+/** `branch_name` is the short name of a branch */
+fn branch_name(branch: &Branch<'_>) -> Result<String> {
+    Ok(branch
+        .name()?
+        .ok_or_else(|| anyhow!("branch name is not UTF-8"))?
+        .to_string())
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,118 @@
+/*! Stopping network operations, on request or when they take too long. */
+use crate::{CodexGitError, NullResult};
+use ansi_term::Colour::*;
+use log::trace;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// tracing macro
+macro_rules! cancel_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", White.on(Red).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `CancelToken` stops the network operations of a [crate::CodexRepoConfig] and the [crate::CodexRepository] made from it, which then fail with [CodexGitError::Cancelled]. Clones of the token share the same flag, so it can be cancelled from another thread.
+
+Clones and fetches are stopped during the transfer. git2 cannot stop a push while the pack is being sent, so a push is stopped before it connects, while authenticating, or when the remote sends a progress message. A cancelled token stays cancelled (and later operations fail at once) until it is [Self::reset]. */
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    /// cancelling the parent cancels this token too
+    parent: Option<Arc<CancelToken>>,
+}
+impl CancelToken {
+    /** `new` makes a token that is not cancelled */
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /** `child` makes a token that is cancelled along with this one, but can also be cancelled (and reset) on its own without affecting this one */
+    pub fn child(&self) -> Self {
+        Self {
+            cancelled: Arc::default(),
+            parent: Some(Arc::new(self.clone())),
+        }
+    }
+    /** `cancel` asks the operations using this token to stop */
+    pub fn cancel(&self) {
+        cancel_trace!("cancel requested");
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    /** `is_cancelled` is whether [Self::cancel] has been called (since the last [Self::reset]), on this token or its parent */
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self.parent.as_ref().is_some_and(|p| p.is_cancelled())
+    }
+    /** `reset` allows operations to run again after a cancellation of this token (not of its parent) */
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+}
+
+/** An `Interrupt` is the cancel token and time limit for one network operation (including any retries). */
+#[derive(Debug, Clone)]
+pub(crate) struct Interrupt {
+    token: CancelToken,
+    started: Instant,
+    timeout: Option<Duration>,
+}
+impl Interrupt {
+    /** `new` starts the clock for an operation */
+    pub(crate) fn new(token: &CancelToken, timeout: Option<Duration>) -> Self {
+        Self {
+            token: token.clone(),
+            started: Instant::now(),
+            timeout,
+        }
+    }
+    /** `check` fails if the operation has been cancelled or has run out of time */
+    pub(crate) fn check(&self) -> NullResult {
+        if self.token.is_cancelled() {
+            return Err(CodexGitError::Cancelled);
+        }
+        match self.timeout {
+            Some(timeout) if self.started.elapsed() >= timeout => {
+                Err(CodexGitError::TimedOut { after: timeout })
+            }
+            _ => Ok(()),
+        }
+    }
+    /** `should_stop` is whether a git2 callback should abort the operation */
+    pub(crate) fn should_stop(&self) -> bool {
+        let stop = self.check().is_err();
+        if stop {
+            cancel_trace!("stopping operation");
+        }
+        stop
+    }
+    /** `remaining` is the time left before the operation times out */
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.timeout
+            .map(|timeout| timeout.saturating_sub(self.started.elapsed()))
+    }
+    /** `explain` replaces the error from an aborted git2 call with the reason it was aborted */
+    pub(crate) fn explain(&self, err: CodexGitError) -> CodexGitError {
+        match self.check() {
+            Err(stopped) => stopped,
+            Ok(()) => err,
+        }
+    }
+    /** `sleep` waits, waking early if the operation is cancelled or runs out of time */
+    pub(crate) fn sleep(&self, delay: Duration) -> NullResult {
+        const SLICE: Duration = Duration::from_millis(50);
+        let until = Instant::now() + delay;
+        loop {
+            self.check()?;
+            let now = Instant::now();
+            if now >= until {
+                return Ok(());
+            }
+            std::thread::sleep(SLICE.min(until - now));
+        }
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,56 @@
+/*! Cherry-picking: applying a commit from another branch onto the current one. */
+use crate::pull::check_conflicts;
+use crate::{CodexGitError, CodexRepository, Result, SyncEvent};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use git2::{Oid, Signature};
+use log::trace;
+
+/// tracing macro
+macro_rules! pick_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Cyan).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+impl CodexRepository {
+    /** `cherry_pick` applies the changes made by a commit (given as a revision, such as `feature~1`) onto the current branch as a new commit, keeping the original author and message with the configured [crate::User] as committer. It fails with [CodexGitError::DirtyWorkingTree] if tracked files have changes that are not committed, and if the commit's changes are already on the branch (so the pick would be empty). It fails with [CodexGitError::MergeConflict] (leaving the conflicts in the working tree, as a merge does) if the changes do not apply cleanly; once they are resolved, [CodexRepository::commit] makes an ordinary commit, so the original author and message are lost. */
+    pub fn cherry_pick(&mut self, rev: &str) -> Result<Oid> {
+        pick_trace!("cherry-picking {}", rev);
+        self.check_on_branch()?;
+        let changed = self.tracked_changes()?;
+        if !changed.is_empty() {
+            return Err(CodexGitError::DirtyWorkingTree { paths: changed });
+        }
+        let commit = self.repo.revparse_single(rev)?.peel_to_commit()?;
+        self.repo.cherrypick(&commit, None)?;
+        check_conflicts(&self.repo.index()?, &self.events)?;
+        let tree = self.repo.find_tree(self.repo.index()?.write_tree()?)?;
+        if tree.id() == self.our_commit()?.tree_id() {
+            self.repo.cleanup_state()?;
+            return Err(anyhow!("{} makes no changes on this branch", commit.id()).into());
+        }
+        let committer = Signature::now(&self.config.user.name, &self.config.user.email)?;
+        let message = commit.message().unwrap_or("").to_string();
+        let oid = self.repo.commit(
+            Some("HEAD"),
+            &commit.author(),
+            &committer,
+            &message,
+            &tree,
+            &[&self.our_commit()?],
+        )?;
+        self.repo.cleanup_state()?;
+        pick_trace!("picked {} as {}", commit.id(), oid);
+        self.events.emit(SyncEvent::Committed {
+            oid: oid.to_string(),
+            message,
+        });
+        self.needs_push = true;
+        Ok(oid)
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,336 @@
+/*! Loading a [CodexRepoConfig] from layers: defaults, a RON file, environment variables and git config. */
+use crate::{
+    AutoAdd, CodexGitError, CodexRepoConfig, ConfigProblem, HttpsAuth, HttpsConfig, ProxySettings,
+    Result, RetryPolicy, SshKeys,
+};
+use ansi_term::Colour::*;
+use getset::{Getters, Setters};
+use log::trace;
+use ron::extensions::Extensions;
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// tracing macro
+macro_rules! config_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Blue).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `ConfigLayer` is where a configuration value came from. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigLayer {
+    /// the built in default
+    Default,
+    /// a RON configuration file
+    File(PathBuf),
+    /// an environment variable
+    Environment(String),
+    /// a git configuration key (from the repository, or the user's global config)
+    GitConfig(String),
+}
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigLayer::Default => write!(f, "default"),
+            ConfigLayer::File(p) => write!(f, "file {}", p.to_string_lossy()),
+            ConfigLayer::Environment(v) => write!(f, "environment variable {}", v),
+            ConfigLayer::GitConfig(k) => write!(f, "git config {}", k),
+        }
+    }
+}
+
+/** `ConfigSources` records which [ConfigLayer] each value of a loaded [CodexRepoConfig] came from. Values are named by field, such as `remote_url` or `user.email`. */
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    sources: BTreeMap<String, ConfigLayer>,
+}
+impl ConfigSources {
+    /** `source` is the layer a value came from ([ConfigLayer::Default] if not set by any layer) */
+    pub fn source(&self, field: &str) -> ConfigLayer {
+        self.sources
+            .get(field)
+            .cloned()
+            .unwrap_or(ConfigLayer::Default)
+    }
+    /** `iter` lists the values that were set by a layer other than the default */
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ConfigLayer)> {
+        self.sources.iter()
+    }
+    fn set(&mut self, field: &str, layer: ConfigLayer) {
+        config_trace!("{} from {}", field, &layer);
+        self.sources.insert(field.to_string(), layer);
+    }
+}
+impl fmt::Display for ConfigSources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (field, layer) in &self.sources {
+            writeln!(f, "{}: {}", field, layer)?;
+        }
+        Ok(())
+    }
+}
+
+/** `PartialUser` is a [crate::User] where either part may be missing */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PartialUser {
+    name: Option<String>,
+    email: Option<String>,
+}
+/** `PartialConfig` is one layer of a [CodexRepoConfig], where anything may be missing */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PartialConfig {
+    user: PartialUser,
+    remote_url: Option<String>,
+    path: Option<PathBuf>,
+    local_name: Option<String>,
+    auto_add: Option<AutoAdd>,
+    ssh_keys: Option<SshKeys>,
+    https: Option<HttpsConfig>,
+    retry: Option<RetryPolicy>,
+    timeout_ms: Option<u64>,
+    journal: Option<bool>,
+    autostash: Option<bool>,
+    verbose: Option<bool>,
+}
+
+/** A `ConfigLoader` builds a [CodexRepoConfig] from its layers. Later layers override earlier ones: defaults, then git config (`user.name` and `user.email` only), then the RON file, then environment variables. */
+#[derive(Debug, Clone, Getters, Setters)]
+#[getset(get = "pub", set = "pub")]
+pub struct ConfigLoader {
+    /// RON file to read, if any
+    file: Option<PathBuf>,
+    /// prefix for environment variables, such as `CODEX_GIT` for `CODEX_GIT_REMOTE_URL`
+    env_prefix: String,
+    /// whether to read the user from git config
+    use_git_config: bool,
+}
+impl Default for ConfigLoader {
+    fn default() -> Self {
+        Self {
+            file: None,
+            env_prefix: "CODEX_GIT".to_string(),
+            use_git_config: true,
+        }
+    }
+}
+impl ConfigLoader {
+    /** `new` makes a loader for a RON file */
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self {
+            file,
+            ..Default::default()
+        }
+    }
+    /** `load` merges the layers into a [CodexRepoConfig]. Environment variables that cannot be parsed are reported as [ConfigProblem::BadEnvironmentValue]. */
+    pub fn load(&self) -> Result<(CodexRepoConfig, ConfigSources)> {
+        let mut config = CodexRepoConfig::default();
+        let mut sources = ConfigSources::default();
+        if let Some(file) = &self.file {
+            config_trace!("reading config file {:?}", file);
+            let text = std::fs::read_to_string(file)?;
+            // values that are present need not be written as `Some(..)`
+            let partial: PartialConfig = ron::Options::default()
+                .with_default_extension(Extensions::IMPLICIT_SOME)
+                .from_str(&text)?;
+            apply(&mut config, &mut sources, partial, &|_| {
+                ConfigLayer::File(file.clone())
+            });
+        }
+        let mut problems = vec![];
+        let env = self.environment_layer(&mut problems);
+        let prefix = self.env_prefix.clone();
+        apply(&mut config, &mut sources, env, &|field| {
+            ConfigLayer::Environment(env_name(&prefix, field))
+        });
+        self.apply_nested_environment(&mut config, &mut sources, &mut problems);
+        if !problems.is_empty() {
+            return Err(CodexGitError::InvalidConfig { problems });
+        }
+        if self.use_git_config {
+            self.apply_git_config(&mut config, &mut sources);
+        }
+        Ok((config, sources))
+    }
+    /** `environment_layer` reads the environment variable layer (except for the settings within [Self::apply_nested_environment]) */
+    fn environment_layer(&self, problems: &mut Vec<ConfigProblem>) -> PartialConfig {
+        let var = |field: &str| self.var(field);
+        PartialConfig {
+            user: PartialUser {
+                name: var("user.name"),
+                email: var("user.email"),
+            },
+            remote_url: var("remote_url"),
+            path: var("path").map(PathBuf::from),
+            local_name: var("local_name"),
+            auto_add: None,
+            ssh_keys: None,
+            https: None,
+            retry: None,
+            timeout_ms: self.parsed_var("timeout_ms", problems),
+            journal: self.flag_var("journal", problems),
+            autostash: self.flag_var("autostash", problems),
+            verbose: self.flag_var("verbose", problems),
+        }
+    }
+    /** `apply_nested_environment` sets single values within nested settings from the environment (the retry attempts, the auto add patterns, and the HTTPS bearer token and proxy), keeping the rest of those settings */
+    fn apply_nested_environment(
+        &self,
+        config: &mut CodexRepoConfig,
+        sources: &mut ConfigSources,
+        problems: &mut Vec<ConfigProblem>,
+    ) {
+        let layer = |field: &str| ConfigLayer::Environment(env_name(&self.env_prefix, field));
+        if let Some(attempts) = self.parsed_var("retry.max_attempts", problems) {
+            config.retry.set_max_attempts(attempts);
+            sources.set("retry.max_attempts", layer("retry.max_attempts"));
+        }
+        if let Some(patterns) = self.var("auto_add") {
+            config.auto_add.set_include(
+                patterns
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+            );
+            sources.set("auto_add.include", layer("auto_add"));
+        }
+        if let Some(token) = self.var("https.token") {
+            config.https.set_auth(HttpsAuth::Bearer { token });
+            sources.set("https.auth", layer("https.token"));
+        }
+        if let Some(proxy) = self.var("https.proxy") {
+            config.https.set_proxy(ProxySettings::Url(proxy));
+            sources.set("https.proxy", layer("https.proxy"));
+        }
+    }
+    /** `parsed_var` reads and parses the environment variable for a field, noting a problem if it cannot be parsed */
+    fn parsed_var<T: FromStr>(&self, field: &str, problems: &mut Vec<ConfigProblem>) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        let value = self.var(field)?;
+        match value.trim().parse() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                problems.push(ConfigProblem::BadEnvironmentValue {
+                    variable: env_name(&self.env_prefix, field),
+                    value,
+                    reason: e.to_string(),
+                });
+                None
+            }
+        }
+    }
+    /** `flag_var` reads the environment variable for a yes or no field (`1`, `true` or `yes`, or `0`, `false` or `no`) */
+    fn flag_var(&self, field: &str, problems: &mut Vec<ConfigProblem>) -> Option<bool> {
+        let value = self.var(field)?;
+        match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" => Some(true),
+            "0" | "false" | "no" => Some(false),
+            _ => {
+                problems.push(ConfigProblem::BadEnvironmentValue {
+                    variable: env_name(&self.env_prefix, field),
+                    value,
+                    reason: "expected true or false".to_string(),
+                });
+                None
+            }
+        }
+    }
+    /** `var` reads the environment variable for a field */
+    fn var(&self, field: &str) -> Option<String> {
+        std::env::var(env_name(&self.env_prefix, field)).ok()
+    }
+    /** `apply_git_config` fills in the user from the repository's git config (or the global one) where no other layer set it */
+    fn apply_git_config(&self, config: &mut CodexRepoConfig, sources: &mut ConfigSources) {
+        let git_config = config
+            .full_path()
+            .ok()
+            .and_then(|p| git2::Repository::open(p).ok())
+            .and_then(|r| r.config().ok())
+            .or_else(|| git2::Config::open_default().ok());
+        let git_config = match git_config {
+            Some(c) => c,
+            None => return,
+        };
+        for (field, key) in [("user.name", "user.name"), ("user.email", "user.email")] {
+            if sources.source(field) != ConfigLayer::Default {
+                continue;
+            }
+            if let Ok(value) = git_config.get_string(key) {
+                match field {
+                    "user.name" => config.user.name = value,
+                    _ => config.user.email = value,
+                }
+                sources.set(field, ConfigLayer::GitConfig(key.to_string()));
+            }
+        }
+    }
+}
+/** `apply` overrides `config` with whatever is in one layer */
+fn apply(
+    config: &mut CodexRepoConfig,
+    sources: &mut ConfigSources,
+    partial: PartialConfig,
+    layer: &dyn Fn(&str) -> ConfigLayer,
+) {
+    macro_rules! take {
+        ($value:expr, $field:expr, $target:expr) => {
+            if let Some(v) = $value {
+                $target = v;
+                sources.set($field, layer($field));
+            }
+        };
+    }
+    take!(partial.user.name, "user.name", config.user.name);
+    take!(partial.user.email, "user.email", config.user.email);
+    take!(partial.remote_url, "remote_url", config.remote_url);
+    take!(partial.path, "path", config.path);
+    take!(
+        partial.local_name.map(Some),
+        "local_name",
+        config.local_name
+    );
+    take!(partial.auto_add, "auto_add", config.auto_add);
+    take!(partial.ssh_keys, "ssh_keys", config.ssh_keys);
+    take!(partial.https, "https", config.https);
+    take!(partial.retry, "retry", config.retry);
+    take!(
+        partial.timeout_ms.map(Some),
+        "timeout_ms",
+        config.timeout_ms
+    );
+    take!(partial.journal, "journal", config.journal);
+    take!(partial.autostash, "autostash", config.autostash);
+    take!(partial.verbose, "verbose", config.verbose);
+}
+/** `env_name` is the environment variable for a field, such as `CODEX_GIT_USER_EMAIL` for `user.email` */
+fn env_name(prefix: &str, field: &str) -> String {
+    format!(
+        "{}_{}",
+        prefix,
+        field.replace('.', "_").to_ascii_uppercase()
+    )
+}
+impl CodexRepoConfig {
+    /** `to_ron` writes the config as RON, leaving out the SSH keys, the HTTPS credentials and any credentials in the proxy URL */
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(to_string_pretty(self, PrettyConfig::new())?)
+    }
+    /** `save` writes the config to a RON file, leaving out the SSH keys, the HTTPS credentials and any credentials in the proxy URL */
+    pub fn save(&self, file: &Path) -> Result<()> {
+        std::fs::write(file, self.to_ron()?)?;
+        Ok(())
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
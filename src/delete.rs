@@ -0,0 +1,211 @@
+/*! Deleting the local copy of a repository, with checks that it is the right directory and that nothing will be lost. */
+use crate::{CodexGitError, CodexRepoConfig, RemoteUrl, Result};
+use ansi_term::Colour::*;
+use chrono::Local;
+use getset::{CopyGetters, Getters, Setters};
+use git2::{BranchType, Repository, StatusOptions};
+use log::trace;
+use std::path::{Path, PathBuf};
+
+/// tracing macro
+macro_rules! delete_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Red).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `DeleteOptions` controls [CodexRepoConfig::delete_repo_with]. */
+#[derive(Debug, Default, Clone, Getters, CopyGetters, Setters)]
+pub struct DeleteOptions {
+    /// delete even if there are uncommitted changes or unpushed commits
+    #[getset(get_copy = "pub", set = "pub")]
+    force: bool,
+    /// move the repository into this directory instead of deleting it
+    #[getset(get = "pub", set = "pub")]
+    trash: Option<PathBuf>,
+}
+
+impl CodexRepoConfig {
+    /** `delete_repo` deletes the repository after checking that it is a git working tree for the [Self::remote_url] with nothing uncommitted or unpushed. */
+    pub fn delete_repo(&self) -> Result<()> {
+        self.delete_repo_with(&DeleteOptions::default())?;
+        Ok(())
+    }
+    /** `delete_repo_with` deletes the repository, or moves it into the trash directory, returning where it was moved to. */
+    pub fn delete_repo_with(&self, options: &DeleteOptions) -> Result<Option<PathBuf>> {
+        let target = self.full_path()?;
+        delete_trace!("deleting {:?} ({:?})", &target, options);
+        self.check_deletable(&target, options.force)?;
+        match &options.trash {
+            None => {
+                std::fs::remove_dir_all(&target)?;
+                Ok(None)
+            }
+            Some(trash) => {
+                std::fs::create_dir_all(trash)?;
+                let moved = trash.join(format!(
+                    "{}-{}",
+                    self.repo_name()?,
+                    Local::now().format("%Y%m%d-%H%M%S%.3f")
+                ));
+                move_dir(&target, &moved, |from, to| std::fs::rename(from, to))?;
+                delete_trace!("moved to {:?}", &moved);
+                Ok(Some(moved))
+            }
+        }
+    }
+    /** `check_deletable` refuses to delete anything but a clean working tree of our remote */
+    fn check_deletable(&self, target: &Path, force: bool) -> Result<()> {
+        if !target.is_dir() {
+            return Err(CodexGitError::NotARepository {
+                path: target.to_path_buf(),
+                reason: "not a directory".to_string(),
+            });
+        }
+        let target = target.canonicalize()?;
+        if let Ok(parent) = self.path.canonicalize() {
+            if parent.starts_with(&target) {
+                return Err(refuse(&target, "it contains the configured path"));
+            }
+        }
+        let repo = Repository::open(&target).map_err(|e| CodexGitError::NotARepository {
+            path: target.clone(),
+            reason: e.message().to_string(),
+        })?;
+        match repo.workdir().map(|w| w.canonicalize()) {
+            Some(Ok(w)) if w == target => {}
+            _ => {
+                return Err(CodexGitError::NotARepository {
+                    path: target,
+                    reason: "not the top of a git working tree".to_string(),
+                })
+            }
+        }
+        let origin_url = match repo.find_remote("origin") {
+            Ok(origin) => origin.url().unwrap_or("").to_string(),
+            Err(_) => String::new(),
+        };
+        if !same_remote(&origin_url, &self.remote_url).unwrap_or(false) {
+            return Err(CodexGitError::RemoteMismatch {
+                expected: self.remote_url.clone(),
+                found: origin_url,
+            });
+        }
+        if force {
+            return Ok(());
+        }
+        let mut status_options = StatusOptions::new();
+        status_options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true);
+        let changed: Vec<String> = repo
+            .statuses(Some(&mut status_options))?
+            .iter()
+            .filter_map(|entry| entry.path().map(|p| p.to_string()))
+            .collect();
+        if !changed.is_empty() {
+            return Err(CodexGitError::DirtyWorkingTree { paths: changed });
+        }
+        let unpushed = unpushed_commits(&repo)?;
+        if unpushed > 0 {
+            return Err(refuse(&target, &format!("{} unpushed commits", unpushed)));
+        }
+        Ok(())
+    }
+}
+/** `unpushed_commits` counts the commits on the current branch that are not on its upstream */
+pub(crate) fn unpushed_commits(repo: &Repository) -> Result<usize> {
+    let head = match repo.head() {
+        Ok(h) => h,
+        // no commits yet
+        Err(_) => return Ok(0),
+    };
+    let local = match head.target() {
+        Some(oid) => oid,
+        None => return Ok(0),
+    };
+    let name = head.shorthand().unwrap_or("main").to_string();
+    let upstream = repo
+        .find_branch(&name, BranchType::Local)
+        .and_then(|b| b.upstream())
+        .ok()
+        .and_then(|u| u.get().target())
+        .or_else(|| {
+            repo.refname_to_id(&format!("refs/remotes/origin/{}", name))
+                .ok()
+        });
+    match upstream {
+        Some(remote) => Ok(repo.graph_ahead_behind(local, remote)?.0),
+        None => {
+            let mut walk = repo.revwalk()?;
+            walk.push(local)?;
+            Ok(walk.count())
+        }
+    }
+}
+/** `move_dir` moves a directory with `rename`, or, when that fails because the destination is on another filesystem, copies it and then removes the original */
+pub(crate) fn move_dir(
+    from: &Path,
+    to: &Path,
+    rename: impl Fn(&Path, &Path) -> std::io::Result<()>,
+) -> Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            delete_trace!("{:?} is on another filesystem, copying", to);
+            if let Err(e) = copy_dir(from, to) {
+                // leave the original as it was
+                let _ = std::fs::remove_dir_all(to);
+                return Err(e.into());
+            }
+            std::fs::remove_dir_all(from)?;
+            Ok(())
+        }
+        other => Ok(other?),
+    }
+}
+/** `copy_dir` copies a directory tree, keeping symbolic links as links */
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if kind.is_symlink() {
+            copy_link(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())
+}
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+#[cfg(not(unix))]
+fn copy_link(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::copy(from, to).map(|_| ())
+}
+/** `same_remote` compares remote URLs, ignoring a `.git` suffix and trailing slashes */
+fn same_remote(a: &str, b: &str) -> Result<bool> {
+    fn normalise(url: &str) -> Result<(String, String)> {
+        let parsed = RemoteUrl::parse(url)?;
+        let path = parsed.path().trim_end_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        Ok((parsed.host().to_ascii_lowercase(), path.to_string()))
+    }
+    Ok(normalise(a)? == normalise(b)?)
+}
+/** `refuse` is the error for a directory that will not be deleted */
+fn refuse(target: &Path, why: &str) -> CodexGitError {
+    CodexGitError::DeleteRefused {
+        path: target.to_path_buf(),
+        reason: why.to_string(),
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
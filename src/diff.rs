@@ -0,0 +1,322 @@
+/*! Differences between commits, the index and the working tree. */
+use crate::{CodexRepository, Result};
+use ansi_term::Colour::*;
+use getset::{CopyGetters, Getters};
+use git2::{Delta, Diff, DiffFindOptions, DiffOptions, Patch};
+use log::trace;
+use std::fmt;
+
+/// tracing macro
+macro_rules! diff_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Purple).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `DiffSide` is one of the two things compared by [CodexRepository::diff]. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffSide {
+    /// the tree of a commit, given as a revision (such as `HEAD`, `ORIG_HEAD` or an OID)
+    Commit(String),
+    /// the index (what would be committed)
+    Index,
+    /// the files in the working tree, including untracked files
+    WorkingTree,
+}
+
+/** `DeltaStatus` is how a file differs. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaStatus {
+    /// only in the new side
+    Added,
+    /// only in the old side
+    Deleted,
+    /// changed contents
+    Modified,
+    /// moved (possibly with changes)
+    Renamed,
+    /// copied from another file
+    Copied,
+    /// changed between a file, a symbolic link and a submodule
+    TypeChange,
+    /// not tracked by git
+    Untracked,
+    /// anything else (such as unreadable or conflicted files)
+    Other,
+}
+impl From<Delta> for DeltaStatus {
+    fn from(delta: Delta) -> Self {
+        match delta {
+            Delta::Added => DeltaStatus::Added,
+            Delta::Deleted => DeltaStatus::Deleted,
+            Delta::Modified => DeltaStatus::Modified,
+            Delta::Renamed => DeltaStatus::Renamed,
+            Delta::Copied => DeltaStatus::Copied,
+            Delta::Typechange => DeltaStatus::TypeChange,
+            Delta::Untracked => DeltaStatus::Untracked,
+            _ => DeltaStatus::Other,
+        }
+    }
+}
+
+/** `LineKind` is whether a line of a hunk is unchanged, added or removed. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    /// in both sides
+    Context,
+    /// only in the new side
+    Added,
+    /// only in the old side
+    Removed,
+}
+
+/** A `DiffLine` is a line of a [DiffHunk]. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct DiffLine {
+    /// unchanged, added or removed
+    #[getset(get_copy = "pub")]
+    kind: LineKind,
+    /// line number in the old side
+    #[getset(get_copy = "pub")]
+    old_lineno: Option<u32>,
+    /// line number in the new side
+    #[getset(get_copy = "pub")]
+    new_lineno: Option<u32>,
+    /// the text of the line without the line end
+    #[getset(get = "pub")]
+    content: String,
+    /// false if this is the last line of the file and it has no line end
+    #[getset(get_copy = "pub")]
+    newline: bool,
+}
+
+/** A `DiffHunk` is a run of changed lines with their context. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct DiffHunk {
+    /// the `@@ -a,b +c,d @@` line
+    #[getset(get = "pub")]
+    header: String,
+    /// first line in the old side
+    #[getset(get_copy = "pub")]
+    old_start: u32,
+    /// number of lines in the old side
+    #[getset(get_copy = "pub")]
+    old_lines: u32,
+    /// first line in the new side
+    #[getset(get_copy = "pub")]
+    new_start: u32,
+    /// number of lines in the new side
+    #[getset(get_copy = "pub")]
+    new_lines: u32,
+    /// the lines
+    #[getset(get = "pub")]
+    lines: Vec<DiffLine>,
+}
+
+/** A `FileDelta` is how one file differs. Its [fmt::Display] is the unified patch for the file. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct FileDelta {
+    /// how the file differs
+    #[getset(get_copy = "pub")]
+    status: DeltaStatus,
+    /// path in the old side, if it is there
+    #[getset(get = "pub")]
+    old_path: Option<String>,
+    /// path in the new side, if it is there
+    #[getset(get = "pub")]
+    new_path: Option<String>,
+    /// whether either side is binary (there are then no hunks)
+    #[getset(get_copy = "pub")]
+    binary: bool,
+    /// the changes, for text files
+    #[getset(get = "pub")]
+    hunks: Vec<DiffHunk>,
+}
+impl FileDelta {
+    /** `path` is the new path, or the old one if the file was deleted */
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or("")
+    }
+}
+impl fmt::Display for FileDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let old = self.old_path.as_deref().unwrap_or_else(|| self.path());
+        let new = self.new_path.as_deref().unwrap_or_else(|| self.path());
+        writeln!(f, "diff --git a/{} b/{}", old, new)?;
+        match self.status {
+            DeltaStatus::Renamed => writeln!(f, "rename from {}\nrename to {}", old, new)?,
+            DeltaStatus::Copied => writeln!(f, "copy from {}\ncopy to {}", old, new)?,
+            _ => {}
+        }
+        let old_name = match self.old_path {
+            Some(_) if self.status != DeltaStatus::Added => format!("a/{}", old),
+            _ => "/dev/null".to_string(),
+        };
+        let new_name = match self.new_path {
+            Some(_) if self.status != DeltaStatus::Deleted => format!("b/{}", new),
+            _ => "/dev/null".to_string(),
+        };
+        if self.binary {
+            return writeln!(f, "Binary files {} and {} differ", old_name, new_name);
+        }
+        if self.hunks.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "--- {}\n+++ {}", old_name, new_name)?;
+        for hunk in &self.hunks {
+            writeln!(f, "{}", hunk.header)?;
+            for line in &hunk.lines {
+                let marker = match line.kind {
+                    LineKind::Context => ' ',
+                    LineKind::Added => '+',
+                    LineKind::Removed => '-',
+                };
+                writeln!(f, "{}{}", marker, line.content)?;
+                if !line.newline {
+                    writeln!(f, "\\ No newline at end of file")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+/** `unified_patch` is the unified patch for all the files, as from `git diff`. */
+pub fn unified_patch(deltas: &[FileDelta]) -> String {
+    deltas.iter().map(|d| d.to_string()).collect()
+}
+
+impl CodexRepository {
+    /** `diff` compares two of a commit, the index and the working tree, detecting renames (including a tracked file moved to a new, untracked, name in the working tree). */
+    pub fn diff(&self, old: &DiffSide, new: &DiffSide) -> Result<Vec<FileDelta>> {
+        diff_trace!("diff {:?} to {:?}", old, new);
+        let mut options = DiffOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        let mut diff = match (old, new) {
+            (DiffSide::Commit(a), DiffSide::Commit(b)) => {
+                let a = self.tree(a)?;
+                let b = self.tree(b)?;
+                self.repo
+                    .diff_tree_to_tree(Some(&a), Some(&b), Some(&mut options))?
+            }
+            (DiffSide::Commit(a), DiffSide::Index) => self.tree_to_index(a, &mut options)?,
+            (DiffSide::Index, DiffSide::Commit(b)) => {
+                self.tree_to_index(b, options.reverse(true))?
+            }
+            (DiffSide::Commit(a), DiffSide::WorkingTree) => {
+                self.tree_to_workdir(a, &mut options)?
+            }
+            (DiffSide::WorkingTree, DiffSide::Commit(b)) => {
+                self.tree_to_workdir(b, options.reverse(true))?
+            }
+            (DiffSide::Index, DiffSide::WorkingTree) => {
+                self.repo.diff_index_to_workdir(None, Some(&mut options))?
+            }
+            (DiffSide::WorkingTree, DiffSide::Index) => self
+                .repo
+                .diff_index_to_workdir(None, Some(options.reverse(true)))?,
+            (DiffSide::Index, DiffSide::Index) | (DiffSide::WorkingTree, DiffSide::WorkingTree) => {
+                return Ok(vec![])
+            }
+        };
+        diff.find_similar(Some(
+            DiffFindOptions::new()
+                .renames(true)
+                .for_untracked(true)
+                .copies(false),
+        ))?;
+        deltas(&diff)
+    }
+    fn tree(&self, rev: &str) -> Result<git2::Tree<'_>> {
+        Ok(self.repo.revparse_single(rev)?.peel_to_tree()?)
+    }
+    fn tree_to_index(&self, rev: &str, options: &mut DiffOptions) -> Result<Diff<'_>> {
+        let tree = self.tree(rev)?;
+        Ok(self
+            .repo
+            .diff_tree_to_index(Some(&tree), None, Some(options))?)
+    }
+    fn tree_to_workdir(&self, rev: &str, options: &mut DiffOptions) -> Result<Diff<'_>> {
+        let tree = self.tree(rev)?;
+        Ok(self
+            .repo
+            .diff_tree_to_workdir_with_index(Some(&tree), Some(options))?)
+    }
+}
+/** `deltas` converts a git2 diff */
+fn deltas(diff: &Diff<'_>) -> Result<Vec<FileDelta>> {
+    let mut found = vec![];
+    for (i, delta) in diff.deltas().enumerate() {
+        let path =
+            |file: git2::DiffFile<'_>| file.path().map(|p| p.to_string_lossy().replace('\\', "/"));
+        let status = DeltaStatus::from(delta.status());
+        let patch = Patch::from_diff(diff, i)?;
+        let mut hunks = vec![];
+        if let Some(patch) = &patch {
+            for h in 0..patch.num_hunks() {
+                let (hunk, line_count) = patch.hunk(h)?;
+                let mut lines: Vec<DiffLine> = vec![];
+                for l in 0..line_count {
+                    let line = patch.line_in_hunk(h, l)?;
+                    let kind = match line.origin() {
+                        ' ' => LineKind::Context,
+                        '+' => LineKind::Added,
+                        '-' => LineKind::Removed,
+                        // the "no newline at end of file" markers
+                        _ => {
+                            if let Some(last) = lines.last_mut() {
+                                last.newline = false;
+                            }
+                            continue;
+                        }
+                    };
+                    let content = String::from_utf8_lossy(line.content());
+                    lines.push(DiffLine {
+                        kind,
+                        old_lineno: line.old_lineno(),
+                        new_lineno: line.new_lineno(),
+                        content: content.trim_end_matches(['\n', '\r']).to_string(),
+                        newline: true,
+                    });
+                }
+                hunks.push(DiffHunk {
+                    header: String::from_utf8_lossy(hunk.header())
+                        .trim_end()
+                        .to_string(),
+                    old_start: hunk.old_start(),
+                    old_lines: hunk.old_lines(),
+                    new_start: hunk.new_start(),
+                    new_lines: hunk.new_lines(),
+                    lines,
+                });
+            }
+        }
+        let binary = delta.flags().is_binary()
+            || delta.old_file().is_binary()
+            || delta.new_file().is_binary();
+        found.push(FileDelta {
+            status,
+            old_path: match status {
+                DeltaStatus::Added | DeltaStatus::Untracked => None,
+                _ => path(delta.old_file()),
+            },
+            new_path: match status {
+                DeltaStatus::Deleted => None,
+                _ => path(delta.new_file()),
+            },
+            binary,
+            hunks,
+        });
+    }
+    diff_trace!("{} files differ", found.len());
+    Ok(found)
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,181 @@
+/*! Errors for this crate, and whether they are worth retrying. */
+use crate::ConfigProblem;
+use git2::{ErrorClass, ErrorCode};
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+/// error for this crate
+#[derive(Error, Debug)]
+pub enum CodexGitError {
+    #[error("git error: {source}")]
+    Git {
+        source: git2::Error,
+        //  backtrace: Backtrace,
+    },
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("RON error: {0}")]
+    Ron(#[from] ron::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+    /// not made by this crate any more (errors have specific variants), kept so that code matching on it still compiles
+    #[error("codex git error")]
+    CodexGit,
+    #[error("utf8 error: {0}")]
+    Utf8Error(#[from] std::str::Utf8Error),
+    /// the remote refused the credentials (or none were available)
+    #[error("authentication failed: {source}")]
+    Authentication { source: git2::Error },
+    /// the remote could not be reached or the connection failed
+    #[error("network error: {source}")]
+    Network { source: git2::Error },
+    /// the remote has commits that are not in the local branch
+    #[error("push rejected, not a fast forward: {0}")]
+    NonFastForward(String),
+    /// a merge left conflicts in these paths
+    #[error("merge conflict in {}", .paths.join(", "))]
+    MergeConflict { paths: Vec<String> },
+    /// the working tree has uncommitted changes in these paths
+    #[error("uncommitted changes in {}", .paths.join(", "))]
+    DirtyWorkingTree { paths: Vec<String> },
+    /// the directory is not (the top of) a git working tree
+    #[error("{path:?} is not a git repository: {reason}")]
+    NotARepository { path: PathBuf, reason: String },
+    /// the repository's remote is not the configured one
+    #[error("remote is {found}, not {expected}")]
+    RemoteMismatch { expected: String, found: String },
+    /// [crate::CodexRepoConfig::delete_repo] will not delete the directory, as it would lose something
+    #[error("will not delete {path:?}: {reason}")]
+    DeleteRefused { path: PathBuf, reason: String },
+    /// a git lock file is held, probably by another process
+    #[error("repository is locked: {0}")]
+    Locked(String),
+    /// the configuration is not usable
+    #[error("invalid configuration: {}", .problems.iter().map(|p| p.to_string()).collect::<Vec<_>>().join("; "))]
+    InvalidConfig { problems: Vec<ConfigProblem> },
+    /// the branch has commits that are not merged anywhere
+    #[error("branch {0} is not merged")]
+    UnmergedBranch(String),
+    /// the commit is already on the remote, so it cannot be undone (revert it instead)
+    #[error("commit {0} has been pushed")]
+    AlreadyPushed(String),
+    /// the branch or its upstream moved after the [crate::ResetConfirmation] was made
+    #[error("the repository changed after the reset was confirmed")]
+    StaleConfirmation,
+    /// `HEAD` is not on a branch (such as after [crate::CodexRepository::checkout_tag]), so nothing can be committed
+    #[error("HEAD is detached at {0}, switch to a branch to make changes")]
+    DetachedHead(String),
+    /// the operation was stopped with a [crate::CancelToken]
+    #[error("operation cancelled")]
+    Cancelled,
+    /// the operation took longer than the configured time limit
+    #[error("operation timed out after {after:?}")]
+    TimedOut { after: Duration },
+}
+
+/** `ErrorClassification` is whether an operation that failed with a [CodexGitError] may succeed if tried again. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClassification {
+    /// the failure may be temporary (such as a network or lock problem)
+    Retryable,
+    /// trying again will fail in the same way
+    Permanent,
+}
+
+impl CodexGitError {
+    /** `classification` is whether the error is worth retrying */
+    pub fn classification(&self) -> ErrorClassification {
+        match self {
+            CodexGitError::Network { .. } | CodexGitError::Locked(_) => {
+                ErrorClassification::Retryable
+            }
+            CodexGitError::IO(e) => match e.kind() {
+                std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::Interrupted
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::WouldBlock => ErrorClassification::Retryable,
+                _ => ErrorClassification::Permanent,
+            },
+            _ => ErrorClassification::Permanent,
+        }
+    }
+    /** `is_retryable` is whether the error is [ErrorClassification::Retryable] */
+    pub fn is_retryable(&self) -> bool {
+        self.classification() == ErrorClassification::Retryable
+    }
+    /** `invalid_config` is the error for a single configuration problem */
+    pub fn invalid_config(problem: ConfigProblem) -> Self {
+        CodexGitError::InvalidConfig {
+            problems: vec![problem],
+        }
+    }
+}
+
+/** Git errors are sorted into the specific variants where the error code or class allows. */
+impl From<git2::Error> for CodexGitError {
+    fn from(source: git2::Error) -> Self {
+        match source.code() {
+            ErrorCode::Auth => return CodexGitError::Authentication { source },
+            ErrorCode::NotFastForward => {
+                return CodexGitError::NonFastForward(source.message().to_string())
+            }
+            ErrorCode::Locked => return CodexGitError::Locked(source.message().to_string()),
+            ErrorCode::Certificate => return CodexGitError::Git { source },
+            _ => {}
+        }
+        match source.class() {
+            // these classes also cover permanent failures (such as a wrong URL or a rejected
+            // SSH key), so only failures of the connection itself are worth retrying
+            ErrorClass::Net
+            | ErrorClass::Http
+            | ErrorClass::Ssh
+            | ErrorClass::Ssl
+            | ErrorClass::Os
+                if is_transport_failure(source.message()) =>
+            {
+                CodexGitError::Network { source }
+            }
+            _ => CodexGitError::Git { source },
+        }
+    }
+}
+/** `is_transport_failure` is whether an error message from libgit2 is about the connection failing (connecting, resolving, a reset or a time out) or an HTTP status that may go away (5xx, 408 or 429) */
+fn is_transport_failure(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    if let Some(status) = http_status(&message) {
+        return status >= 500 || status == 408 || status == 429;
+    }
+    [
+        "failed to connect",
+        "failed to resolve",
+        "could not resolve",
+        "connection refused",
+        "connection reset",
+        "connection aborted",
+        "timed out",
+        "network is unreachable",
+        "host is unreachable",
+        "broken pipe",
+        "early eof",
+        "unexpected eof",
+    ]
+    .iter()
+    .any(|m| message.contains(m))
+}
+/** `http_status` finds the status code in a libgit2 message such as `unexpected http status code: 404` */
+fn http_status(message: &str) -> Option<u16> {
+    let at = message.find("status code")? + "status code".len();
+    message[at..]
+        .trim_start_matches([':', ' '])
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,140 @@
+/*! Events for what a [crate::CodexRepository] has done, for auditing and for the UI, with a journal of them in the `.git` directory. */
+use crate::Result;
+use ansi_term::Colour::*;
+use chrono::Local;
+use getset::Getters;
+use log::{error, trace};
+use ron::ser::to_string;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// tracing macro
+macro_rules! events_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(White).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `JOURNAL_FILE` is the name of the journal file in the `.git` directory. */
+pub const JOURNAL_FILE: &str = "codex-journal.ron";
+
+/** A `MergeKind` is what a fetch did to the local branch. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeKind {
+    /// nothing to merge
+    UpToDate,
+    /// the local branch was moved to the remote commit
+    FastForward,
+    /// a merge commit was made
+    Normal,
+}
+
+/** A `SyncEvent` is something a [crate::CodexRepository] did. */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncEvent {
+    /// paths were added to the index
+    Staged { paths: Vec<String> },
+    /// a commit was made
+    Committed { oid: String, message: String },
+    /// fetched changes were merged into the local branch, `oid` is the new head
+    Merged { kind: MergeKind, oid: String },
+    /// a push updated these references on the remote
+    Pushed { refs: Vec<String> },
+    /// a merge stopped with conflicts in these paths
+    Conflict { paths: Vec<String> },
+}
+
+/** A `SyncSubscriber` is told about each [SyncEvent]. It is called as the events happen, so it should return quickly. */
+pub trait SyncSubscriber: Send + Sync {
+    /** `event` is called for each event */
+    fn event(&self, event: &SyncEvent);
+}
+
+/** A `JournalEntry` is a line of the journal. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
+pub struct JournalEntry {
+    /// when the event happened (RFC 3339)
+    time: String,
+    /// what happened
+    event: SyncEvent,
+}
+
+/** A `Journal` is a [SyncSubscriber] that appends each event as a line of RON to a file. */
+pub struct Journal {
+    path: PathBuf,
+    // only one thread appends at a time
+    lock: Mutex<()>,
+}
+impl Journal {
+    /** `new` makes a journal writing to `path` */
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+    /** `append` writes an entry for an event */
+    fn append(&self, event: &SyncEvent) -> Result<()> {
+        let entry = JournalEntry {
+            time: Local::now().to_rfc3339(),
+            event: event.clone(),
+        };
+        let line = to_string(&entry)?;
+        let _guard = self.lock.lock();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+    /** `read` reads all the entries in a journal file */
+    pub fn read(path: &Path) -> Result<Vec<JournalEntry>> {
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let text = std::fs::read_to_string(path)?;
+        let mut entries = vec![];
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            entries.push(ron::from_str(line)?);
+        }
+        Ok(entries)
+    }
+}
+impl SyncSubscriber for Journal {
+    fn event(&self, event: &SyncEvent) {
+        if let Err(e) = self.append(event) {
+            error!("cannot write journal {:?}: {}", &self.path, e);
+        }
+    }
+}
+
+/** `Subscribers` are the [SyncSubscriber]s of a config or repository. */
+#[derive(Clone, Default)]
+pub(crate) struct Subscribers(Vec<Arc<dyn SyncSubscriber>>);
+impl fmt::Debug for Subscribers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({} subscribers)", self.0.len())
+    }
+}
+impl Subscribers {
+    pub(crate) fn add(&mut self, subscriber: Arc<dyn SyncSubscriber>) {
+        self.0.push(subscriber);
+    }
+    /** `emit` tells all the subscribers about an event */
+    pub(crate) fn emit(&self, event: SyncEvent) {
+        events_trace!("event {:?}", &event);
+        for subscriber in &self.0 {
+            subscriber.event(&event);
+        }
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
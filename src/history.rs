@@ -0,0 +1,202 @@
+/*! Reading the commit history of a [CodexRepository]. */
+use crate::{CodexRepository, Result, User};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use getset::{CopyGetters, Getters, Setters};
+use git2::{Commit, DiffOptions, DiffStats, Oid, Sort, Time};
+use log::trace;
+
+/// tracing macro
+macro_rules! history_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Cyan).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `LogOptions` selects the commits listed by [CodexRepository::log]. By default it lists all the commits reachable from `HEAD`, newest first. */
+#[derive(Debug, Clone, Default, Getters, CopyGetters, Setters)]
+pub struct LogOptions {
+    /// reference or revision to start from (such as `refs/remotes/origin/main` or an OID), `HEAD` if not set
+    #[getset(get = "pub", set = "pub")]
+    start: Option<String>,
+    /// only commits that change one of these paths (git pathspecs), compared with each parent (only the first with `first_parent`)
+    #[getset(get = "pub", set = "pub")]
+    paths: Vec<String>,
+    /// only commits whose author name or email contains this
+    #[getset(get = "pub", set = "pub")]
+    author: Option<String>,
+    /// only commits made at or after this time
+    #[getset(get_copy = "pub", set = "pub")]
+    since: Option<DateTime<FixedOffset>>,
+    /// only commits made at or before this time
+    #[getset(get_copy = "pub", set = "pub")]
+    until: Option<DateTime<FixedOffset>>,
+    /// follow only the first parent of merges
+    #[getset(get_copy = "pub", set = "pub")]
+    first_parent: bool,
+    /// number of matching commits to leave out (for the later pages)
+    #[getset(get_copy = "pub", set = "pub")]
+    skip: usize,
+    /// maximum number of commits to list (the page size)
+    #[getset(get_copy = "pub", set = "pub")]
+    limit: Option<usize>,
+}
+
+/** A `CommitSummary` describes a commit for a history view. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct CommitSummary {
+    /// the commit
+    #[getset(get_copy = "pub")]
+    oid: Oid,
+    /// who wrote the change
+    #[getset(get = "pub")]
+    author: User,
+    /// when the change was committed
+    #[getset(get_copy = "pub")]
+    time: DateTime<FixedOffset>,
+    /// the full commit message
+    #[getset(get = "pub")]
+    message: String,
+    /// the parent commits, the first is the branch the commit was made on
+    #[getset(get = "pub")]
+    parents: Vec<Oid>,
+    /// number of paths changed from the first parent
+    #[getset(get_copy = "pub")]
+    files_changed: usize,
+    /// lines added
+    #[getset(get_copy = "pub")]
+    insertions: usize,
+    /// lines removed
+    #[getset(get_copy = "pub")]
+    deletions: usize,
+}
+impl CommitSummary {
+    /** `summary` is the first line of the message */
+    pub fn summary(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+}
+
+impl CodexRepository {
+    /** `log` lists the commits selected by the options, newest first. */
+    pub fn log(&self, options: &LogOptions) -> Result<Vec<CommitSummary>> {
+        history_trace!("log {:?}", options);
+        let start = options.start.as_deref().unwrap_or("HEAD");
+        let start = self.repo.revparse_single(start)?.peel_to_commit()?.id();
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        walk.push(start)?;
+        if options.first_parent {
+            walk.simplify_first_parent()?;
+        }
+        let mut skipped = 0;
+        let mut found = vec![];
+        for oid in walk {
+            if options.limit.is_some_and(|limit| found.len() >= limit) {
+                break;
+            }
+            let commit = self.repo.find_commit(oid?)?;
+            let time = to_datetime(commit.time())?;
+            if options.since.is_some_and(|since| time < since)
+                || options.until.is_some_and(|until| time > until)
+            {
+                continue;
+            }
+            if let Some(author) = &options.author {
+                let signature = commit.author();
+                let name = signature.name().unwrap_or("");
+                let email = signature.email().unwrap_or("");
+                if !name.contains(author.as_str()) && !email.contains(author.as_str()) {
+                    continue;
+                }
+            }
+            if !options.paths.is_empty() && !self.touches(&commit, options)? {
+                continue;
+            }
+            if skipped < options.skip {
+                skipped += 1;
+                continue;
+            }
+            found.push(self.summarise(&commit)?);
+        }
+        history_trace!("log found {} commits", found.len());
+        Ok(found)
+    }
+    /** `summarise` makes the [CommitSummary] for a commit */
+    fn summarise(&self, commit: &Commit<'_>) -> Result<CommitSummary> {
+        let author = commit.author();
+        let (files_changed, insertions, deletions) = self.changes(commit, &[])?;
+        Ok(CommitSummary {
+            oid: commit.id(),
+            author: User::new(author.name().unwrap_or(""), author.email().unwrap_or("")),
+            time: to_datetime(commit.time())?,
+            message: commit.message().unwrap_or("").to_string(),
+            parents: commit.parent_ids().collect(),
+            files_changed,
+            insertions,
+            deletions,
+        })
+    }
+    /** `touches` is whether a commit changes one of the paths in the options, compared with any of its parents (only the first if [LogOptions::first_parent] is set), so a merge matches if either side changed them */
+    fn touches(&self, commit: &Commit<'_>, options: &LogOptions) -> Result<bool> {
+        if commit.parent_count() == 0 {
+            return Ok(self
+                .diff_paths(None, commit, &options.paths)?
+                .files_changed()
+                > 0);
+        }
+        let parents = if options.first_parent {
+            1
+        } else {
+            commit.parent_count()
+        };
+        for parent in commit.parents().take(parents) {
+            let stats = self.diff_paths(Some(&parent), commit, &options.paths)?;
+            if stats.files_changed() > 0 {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    /** `changes` counts the paths changed, lines added and lines removed by a commit (compared with its first parent), only in `paths` if there are any */
+    fn changes(&self, commit: &Commit<'_>, paths: &[String]) -> Result<(usize, usize, usize)> {
+        let stats = self.diff_paths(commit.parents().next().as_ref(), commit, paths)?;
+        Ok((stats.files_changed(), stats.insertions(), stats.deletions()))
+    }
+    /** `diff_paths` is the [DiffStats] of a commit against a parent (or the empty tree), only in `paths` if there are any */
+    fn diff_paths(
+        &self,
+        parent: Option<&Commit<'_>>,
+        commit: &Commit<'_>,
+        paths: &[String],
+    ) -> Result<DiffStats> {
+        let parent_tree = match parent {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let mut diff_options = DiffOptions::new();
+        for path in paths {
+            diff_options.pathspec(path);
+        }
+        let diff = self.repo.diff_tree_to_tree(
+            parent_tree.as_ref(),
+            Some(&commit.tree()?),
+            Some(&mut diff_options),
+        )?;
+        Ok(diff.stats()?)
+    }
+}
+/** `to_datetime` converts a git time, failing if it is out of range */
+pub(crate) fn to_datetime(time: Time) -> Result<DateTime<FixedOffset>> {
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60)
+        .ok_or_else(|| anyhow!("time offset {} is out of range", time.offset_minutes()))?;
+    Ok(offset
+        .timestamp_opt(time.seconds(), 0)
+        .single()
+        .ok_or_else(|| anyhow!("time {} is out of range", time.seconds()))?)
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,242 @@
+/*! HTTPS access to the remote: token authentication, certificate checks and proxy settings. */
+use crate::{CodexGitError, ConfigProblem, NullResult};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use getset::{Getters, Setters};
+use git2::cert::Cert;
+use git2::{Cred, ProxyOptions};
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// tracing macro
+macro_rules! https_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Green).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `HttpsAuth` is the authentication used for an HTTP(S) remote. */
+#[derive(Debug, Default, Clone, Deserialize)]
+pub enum HttpsAuth {
+    /// no credentials (or whatever the git credential helpers provide)
+    #[default]
+    None,
+    /// HTTP basic authentication, the password can be an access token
+    Basic { user: String, password: String },
+    /// an `Authorization: Bearer` header
+    Bearer { token: String },
+}
+
+/** `ProxySettings` controls the proxy used for an HTTP(S) remote. */
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub enum ProxySettings {
+    /// connect directly
+    #[default]
+    None,
+    /// detect the proxy from the git configuration and the environment
+    Auto,
+    /// use this proxy URL (which may include credentials)
+    Url(String),
+}
+
+impl ProxySettings {
+    /** `without_credentials` is the setting with any user and password removed from the proxy URL */
+    pub fn without_credentials(&self) -> ProxySettings {
+        match self {
+            ProxySettings::Url(url) => {
+                let start = url.find("://").map(|i| i + 3).unwrap_or(0);
+                let end = url[start..]
+                    .find('/')
+                    .map(|i| start + i)
+                    .unwrap_or(url.len());
+                match url[start..end].rfind('@') {
+                    Some(at) => {
+                        ProxySettings::Url(format!("{}{}", &url[..start], &url[start + at + 1..]))
+                    }
+                    None => self.clone(),
+                }
+            }
+            other => other.clone(),
+        }
+    }
+}
+/** `serialize_proxy` writes the proxy settings without credentials */
+fn serialize_proxy<S: serde::Serializer>(
+    proxy: &ProxySettings,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    proxy.without_credentials().serialize(serializer)
+}
+
+/** An `HttpsConfig` is the HTTP(S) part of a [crate::CodexRepoConfig]. */
+#[derive(Debug, Default, Clone, Getters, Setters, Deserialize, Serialize)]
+#[getset(get = "pub", set = "pub")]
+pub struct HttpsConfig {
+    /// credentials for the remote
+    #[serde(default, skip_serializing)]
+    auth: HttpsAuth,
+    /// CA certificate bundle (a PEM file or a directory of certificates) trusted as well as the system one. libgit2 keeps it for the whole process, so every HTTPS remote used by the process must have the same bundle (or none before the first is used).
+    #[serde(default)]
+    ca_bundle: Option<PathBuf>,
+    /// SHA-256 fingerprint (hex, colons optional) of the server certificate, which is then accepted whatever its chain
+    #[serde(default)]
+    pinned_certificate: Option<String>,
+    /// proxy for the remote (saved without any credentials in its URL)
+    #[serde(default, serialize_with = "serialize_proxy")]
+    proxy: ProxySettings,
+}
+impl HttpsConfig {
+    /** `proxy_options` makes the [ProxyOptions] for fetch and push */
+    pub(crate) fn proxy_options(&self) -> ProxyOptions<'static> {
+        let mut po = ProxyOptions::new();
+        match &self.proxy {
+            ProxySettings::None => {}
+            ProxySettings::Auto => {
+                po.auto();
+            }
+            ProxySettings::Url(url) => {
+                po.url(url);
+            }
+        }
+        po
+    }
+    /** `custom_headers` are the extra HTTP headers sent to the remote */
+    pub(crate) fn custom_headers(&self) -> Vec<String> {
+        match &self.auth {
+            HttpsAuth::Bearer { token } => vec![format!("Authorization: Bearer {}", token)],
+            _ => vec![],
+        }
+    }
+    /** `has_credentials` is whether the config supplies credentials itself */
+    pub(crate) fn has_credentials(&self) -> bool {
+        !matches!(self.auth, HttpsAuth::None)
+    }
+    /** `credential` returns the user and password credential, if any */
+    pub(crate) fn credential(&self) -> Option<std::result::Result<Cred, git2::Error>> {
+        match &self.auth {
+            HttpsAuth::Basic { user, password } => Some(Cred::userpass_plaintext(user, password)),
+            _ => None,
+        }
+    }
+    /** `apply_ca_bundle` makes libgit2 trust the configured CA bundle for an HTTP(S) remote. libgit2 keeps the bundle for the rest of the process and cannot forget it, so once one is in use, a remote configured with another bundle or none fails with [ConfigProblem::CaBundleInUse] rather than quietly trusting it. */
+    pub(crate) fn apply_ca_bundle(&self, remote_url: &str) -> NullResult {
+        if !is_http_url(remote_url) {
+            return Ok(());
+        }
+        if let Some(bundle) = &self.ca_bundle {
+            if !bundle.exists() {
+                return Err(CodexGitError::invalid_config(
+                    ConfigProblem::MissingCaBundle(bundle.clone()),
+                ));
+            }
+        }
+        let mut active = ACTIVE_CA_BUNDLE
+            .lock()
+            .map_err(|_| anyhow!("CA bundle lock poisoned"))?;
+        let bundle = match ca_bundle_to_apply(&active, self.ca_bundle.as_ref())
+            .map_err(CodexGitError::invalid_config)?
+        {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+        https_trace!("using CA bundle {:?}", bundle);
+        let location = CString::new(bundle.to_string_lossy().as_bytes())
+            .map_err(|e| CodexGitError::Other(anyhow!(e)))?;
+        let (file, dir): (*const c_char, *const c_char) = if bundle.is_dir() {
+            (std::ptr::null(), location.as_ptr())
+        } else {
+            (location.as_ptr(), std::ptr::null())
+        };
+        libgit2_sys::init();
+        let rc = unsafe {
+            libgit2_sys::git_libgit2_opts(
+                libgit2_sys::GIT_OPT_SET_SSL_CERT_LOCATIONS as c_int,
+                file,
+                dir,
+            )
+        };
+        if rc < 0 {
+            return Err(git2::Error::last_error(rc)
+                .unwrap_or_else(|| git2::Error::from_str("cannot set CA bundle"))
+                .into());
+        }
+        *active = Some(bundle.clone());
+        Ok(())
+    }
+    /** `check_certificate` decides whether to accept a server certificate, only used when a certificate is pinned and the remote is HTTP(S) */
+    pub(crate) fn check_certificate(&self, cert: &Cert<'_>, host: &str) -> bool {
+        self.accepts_certificate(cert.as_x509().map(|x509| x509.data()), host)
+    }
+    /** `accepts_certificate` decides on the DER data of an X.509 certificate; anything else (such as an SSH host key) cannot match a pin, so it is refused */
+    pub(crate) fn accepts_certificate(&self, der: Option<&[u8]>, host: &str) -> bool {
+        match der {
+            Some(der) => self.matches_pin(der, host),
+            None => {
+                error!(
+                    "certificate for {} is not X.509, it cannot match the pin",
+                    host
+                );
+                false
+            }
+        }
+    }
+    /** `matches_pin` is whether DER certificate data has the pinned fingerprint (or nothing is pinned) */
+    pub(crate) fn matches_pin(&self, der: &[u8], host: &str) -> bool {
+        let pin = match &self.pinned_certificate {
+            Some(p) => normalise_fingerprint(p),
+            None => return true,
+        };
+        let found = fingerprint(der);
+        if found == pin {
+            https_trace!("pinned certificate matches for {}", host);
+            true
+        } else {
+            error!(
+                "certificate for {} has fingerprint {}, expected {}",
+                host, found, pin
+            );
+            false
+        }
+    }
+}
+/// the CA bundle libgit2 has been given, if any
+static ACTIVE_CA_BUNDLE: Mutex<Option<PathBuf>> = Mutex::new(None);
+/** `ca_bundle_to_apply` is the bundle that must be given to libgit2 for a remote that wants `wanted` when `active` is already in use: none if nothing changes, or the problem if the bundle in use cannot be taken back */
+pub(crate) fn ca_bundle_to_apply<'a>(
+    active: &Option<PathBuf>,
+    wanted: Option<&'a PathBuf>,
+) -> std::result::Result<Option<&'a PathBuf>, ConfigProblem> {
+    match (active, wanted) {
+        (None, wanted) => Ok(wanted),
+        (Some(a), Some(w)) if a == w => Ok(None),
+        (Some(a), _) => Err(ConfigProblem::CaBundleInUse(a.clone())),
+    }
+}
+/** `is_http_url` detects whether a remote URL uses HTTP(S) */
+pub(crate) fn is_http_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    lower.starts_with("https://") || lower.starts_with("http://")
+}
+/** `fingerprint` is the SHA-256 fingerprint of DER certificate data as lower case hex */
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+/** `normalise_fingerprint` removes separators and case from a hex fingerprint */
+fn normalise_fingerprint(fp: &str) -> String {
+    fp.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,61 @@
+/*! Generation and export of SSH keys for the remote. */
+use crate::{Result, SshKeys, User};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use log::trace;
+use ssh_key::rand_core::OsRng;
+use ssh_key::{Algorithm, HashAlg, LineEnding, PrivateKey, PublicKey};
+
+/// tracing macro
+macro_rules! keys_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Yellow).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+impl SshKeys {
+    /** `generate` makes a new ed25519 key pair in OpenSSH format, commented with the [User]. If there is a `passphrase`, the private key is encrypted with it. */
+    pub fn generate(user: &User, passphrase: Option<&str>) -> Result<Self> {
+        let comment = user.key_comment();
+        keys_trace!("generating ed25519 key for {}", &comment);
+        let mut private =
+            PrivateKey::random(&mut OsRng, Algorithm::Ed25519).map_err(|e| anyhow!(e))?;
+        private.set_comment(&comment);
+        let public = private.public_key().to_openssh().map_err(|e| anyhow!(e))?;
+        let private = match passphrase {
+            Some(p) => private.encrypt(&mut OsRng, p).map_err(|e| anyhow!(e))?,
+            None => private,
+        };
+        let private = private
+            .to_openssh(LineEnding::LF)
+            .map_err(|e| anyhow!(e))?
+            .to_string();
+        Ok(Self {
+            public,
+            private,
+            passphrase: passphrase.map(|p| p.to_string()),
+        })
+    }
+    /** `authorized_key` is the public key as a line for the server's `authorized_keys` file */
+    pub fn authorized_key(&self) -> Result<String> {
+        Ok(self.public_key()?.to_openssh().map_err(|e| anyhow!(e))?)
+    }
+    /** `fingerprint` is the SHA-256 fingerprint of the public key as shown by `ssh-keygen -l` */
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(self.public_key()?.fingerprint(HashAlg::Sha256).to_string())
+    }
+    /** `public_key` parses the public key */
+    fn public_key(&self) -> Result<PublicKey> {
+        Ok(PublicKey::from_openssh(self.public.trim()).map_err(|e| anyhow!(e))?)
+    }
+}
+impl User {
+    /** `key_comment` is the comment for SSH keys belonging to this user */
+    fn key_comment(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -1,45 +1,75 @@
-/*! codex-git is a simplified wrapper for [git2]. Most of the code is in this file. */
+/*! codex-git is a simplified wrapper for [git2]. This file has [CodexRepoConfig] and the core of [CodexRepository]: cloning, committing, fetching and pushing. Each of the other features (history, branches, the background watcher and sync engine and so on) is in its own module. */
 
 //#![feature(backtrace)]
 use ansi_term::Colour::*;
 use anyhow::anyhow;
-use anyhow::Context;
 use getset::{CopyGetters, Getters, Setters};
-use git2::IndexAddOption;
 use git2::{
-    build::RepoBuilder, Commit, Cred, CredentialType, Direction, FetchOptions, Index, ObjectType,
-    Oid, PushOptions, RemoteCallbacks, Repository, Signature, Tree,
+    build::RepoBuilder, Commit, Cred, CredentialType, ErrorClass, ErrorCode, FetchOptions, Index,
+    ObjectType, Oid, PushOptions, RemoteCallbacks, Repository, RepositoryState, Signature, Tree,
 };
 use git2_credentials::CredentialHandler;
 use log::{error, trace};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::path::PathBuf;
-use thiserror::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+mod auto_add;
+mod blame;
+mod branch;
+mod cancel;
+mod cherry_pick;
+mod config;
+mod delete;
+mod diff;
+mod error;
+mod events;
+mod history;
+mod https;
+mod keys;
+mod progress;
 mod pull;
+mod reflog;
+mod remote_url;
+mod retry;
+mod revision;
+mod stash;
+mod sync;
+mod tag;
+mod undo;
+mod validate;
+#[cfg(feature = "watch")]
+mod watch;
+pub use auto_add::AutoAdd;
+pub use blame::BlameRange;
+pub use branch::BranchInfo;
+use branch::REMOTE;
+pub use cancel::CancelToken;
+use cancel::Interrupt;
+pub use config::{ConfigLayer, ConfigLoader, ConfigSources};
+pub use delete::DeleteOptions;
+pub use diff::{unified_patch, DeltaStatus, DiffHunk, DiffLine, DiffSide, FileDelta, LineKind};
+pub use error::{CodexGitError, ErrorClassification};
+use events::Subscribers;
+pub use events::{Journal, JournalEntry, MergeKind, SyncEvent, SyncSubscriber, JOURNAL_FILE};
+pub use history::{CommitSummary, LogOptions};
+pub use https::{HttpsAuth, HttpsConfig, ProxySettings};
+use progress::{Observer, Reporter};
+pub use progress::{ProgressObserver, ProgressPhase};
+pub use reflog::{Recovery, ReflogEntry};
+pub use remote_url::{RemoteUrl, UrlKind};
+pub use retry::RetryPolicy;
+pub use stash::{StashEntry, StashOutcome};
+pub use sync::{SyncHandle, SyncOptions, SyncState};
+pub use tag::TagInfo;
+pub use undo::{ResetConfirmation, ResetMode};
+pub use validate::{ConfigProblem, RemoteRef};
+#[cfg(feature = "watch")]
+pub use watch::{WatchHandle, WatchOptions};
 
 #[cfg(test)]
 mod tests;
-/// error for this crate
-#[derive(Error, Debug)]
-pub enum CodexGitError {
-    #[error("git error")]
-    Git {
-        #[from]
-        source: git2::Error,
-        //  backtrace: Backtrace,
-    },
-    #[error("IO error")]
-    IO(#[from] std::io::Error),
-    #[error("RON error")]
-    Ron(#[from] ron::Error),
-    #[error(transparent)]
-    Other(#[from] anyhow::Error),
-    #[error("codex git error")]
-    CodexGit,
-    #[error("utf8 error")]
-    Utf8Error(std::str::Utf8Error),
-}
 /// results for this crate
 pub type Result<T> = std::result::Result<T, CodexGitError>;
 /// None or error
@@ -54,17 +84,21 @@ macro_rules! git_trace {
 }
 
 /** An `SshKeys` stores the SSH keys for the remote repository. */
-#[derive(Debug, Default, Clone, Setters, Deserialize)]
-#[getset(set = "pub")]
+#[derive(Debug, Default, Clone, Getters, Setters, Deserialize)]
+#[getset(get = "pub", set = "pub")]
 pub struct SshKeys {
     /// public key
     public: String,
     /// private key
     private: String,
+    /// passphrase for the private key, if it is encrypted
+    #[serde(default)]
+    passphrase: Option<String>,
 }
 //impl SshKeys {}
 /** `User` is a git user (user name and email)*/
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Getters, Deserialize, Serialize)]
+#[getset(get = "pub")]
 pub struct User {
     name: String,
     email: String,
@@ -79,7 +113,7 @@ impl User {
 }
 
 /** A `CodexRepoConfig` is the parameters for making a [CodexRepository].  */
-#[derive(Clone, Setters, Default, Deserialize, Debug)]
+#[derive(Clone, Setters, Default, Deserialize, Serialize, Debug)]
 pub struct CodexRepoConfig {
     /// user name and email for Git commits
     #[getset(set = "pub")]
@@ -90,31 +124,70 @@ pub struct CodexRepoConfig {
     /// where to put the files on disk (excluding the repo name)
     #[getset(set = "pub")]
     path: PathBuf,
-    /// paths to add automatically
+    /// directory name for the repository on disk, instead of the name from the [Self::remote_url]
+    #[getset(set = "pub")]
+    #[serde(default)]
+    local_name: Option<String>,
+    /// which new and changed files to stage when committing
     #[getset(set = "pub")]
-    #[serde(default, skip)]
-    auto_add: Vec<String>,
+    #[serde(default)]
+    auto_add: AutoAdd,
     /// SSH keys for the remote
     #[getset(set = "pub")]
     #[serde(default, skip_serializing)]
     ssh_keys: SshKeys,
+    /// HTTPS credentials, certificates and proxy for the remote
+    #[getset(set = "pub")]
+    #[serde(default)]
+    https: HttpsConfig,
+    /// retries for the network part of clone, fetch and push
+    #[getset(set = "pub")]
+    #[serde(default)]
+    retry: RetryPolicy,
+    /// time limit for each network operation (including any retries), in milliseconds, checked whenever git2 reports progress
+    #[getset(set = "pub")]
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// stops network operations when cancelled
+    #[getset(set = "pub")]
+    #[serde(skip)]
+    cancel: CancelToken,
+    /// told about the progress of network operations
+    #[serde(skip)]
+    observer: Observer,
+    /// told about each [SyncEvent]
+    #[serde(skip)]
+    subscribers: Subscribers,
+    /// append the [SyncEvent]s to a journal file ([JOURNAL_FILE]) in the `.git` directory
+    #[getset(set = "pub")]
+    #[serde(default)]
+    journal: bool,
+    /// stash uncommitted changes to tracked files while [CodexRepository::fetch] merges, then re-apply them
+    #[getset(set = "pub")]
+    #[serde(default)]
+    autostash: bool,
     /// print more messages
     #[serde(default)]
     verbose: bool,
 }
 impl CodexRepoConfig {
-    /** `repo_name` is the name of the repository */
+    /** `remote` is the parsed [Self::remote_url] */
+    pub fn remote(&self) -> Result<RemoteUrl> {
+        RemoteUrl::parse(&self.remote_url)
+    }
+    /** `repo_name` is the name of the repository directory, the `local_name` if set, otherwise from the remote URL without any `.git` */
     pub fn repo_name(&self) -> Result<String> {
-        let parts = self.remote_url.split("/");
-        Ok(parts.last().ok_or(CodexGitError::CodexGit)?.to_string())
+        match &self.local_name {
+            Some(name) => {
+                remote_url::check_dir_name(name)?;
+                Ok(name.clone())
+            }
+            None => self.remote()?.repo_name(),
+        }
     }
     /** `full_path` is the full path of the head of the repository on disk including the [Self::repo_name()] */
     pub fn full_path(&self) -> Result<PathBuf> {
-        Ok(PathBuf::from(format!(
-            "{}/{}",
-            self.path.to_string_lossy(),
-            self.repo_name()?
-        )))
+        Ok(self.path.join(self.repo_name()?))
     }
     /** `has_repository` detects whether a [CodexRepository] exists for this [CodexRepoConfig]. */
     pub fn has_repository(&self) -> Result<bool> {
@@ -130,60 +203,137 @@ impl CodexRepoConfig {
             Ok(true)
         }
     }
-    /** `delete_repo` deletes the repository. */
-    pub fn delete_repo(&self) -> Result<()> {
-        std::fs::remove_dir_all(self.full_path()?)?;
-        Ok(())
-    }
     /** `clone_repo` creates a [CodexRepository] and clones the repository from the remote (a Git clone, not a Rust clone). */
     pub fn clone_repo(&mut self) -> Result<CodexRepository> {
         git_trace!("cloning repo {:?} to {:?}", &self.remote_url, &self.path);
-        let fetch_options = self.fetch_options()?;
-        let repo = RepoBuilder::new()
-            .bare(false)
-            .fetch_options(fetch_options)
-            .clone(&self.remote_url, &self.full_path()?)?;
+        let full_path = self.full_path()?;
+        let interrupt = self.interrupt();
+        let progress = self.observer.reporter();
+        // an aborted clone removes the directory it created
+        let repo = self.retry.run("clone", &interrupt, || {
+            progress.enter(ProgressPhase::Connecting);
+            Ok(RepoBuilder::new()
+                .bare(false)
+                .fetch_options(self.fetch_options(&interrupt, &progress)?)
+                .with_checkout(progress.checkout())
+                .clone(&self.remote_url, &full_path)?)
+        })?;
+        progress.enter(ProgressPhase::Done);
         git_trace!("repo cloned");
         Ok(CodexRepository::new(repo, self))
     }
     /** `open` opens an existing [CodexRepository]. */
     pub fn open(&self) -> Result<CodexRepository> {
         git_trace!("opening existing repo {:?}", &self.full_path()?);
-        let repo = Repository::open(self.full_path()?)?;
+        let path = self.full_path()?;
+        let repo = Repository::open(&path).map_err(|e| match e.code() {
+            ErrorCode::NotFound => CodexGitError::NotARepository {
+                path,
+                reason: e.message().to_string(),
+            },
+            _ => e.into(),
+        })?;
         // git_trace!("repo opened");
         Ok(CodexRepository::new(repo, self))
     }
+    /** `cancel_token` is a [CancelToken] that stops the network operations using this config (and any [CodexRepository] made from it) */
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+    /** `set_progress_observer` sets the [ProgressObserver] for network operations using this config (and any [CodexRepository] made from it afterwards) */
+    pub fn set_progress_observer(&mut self, observer: Arc<dyn ProgressObserver>) -> &mut Self {
+        self.observer = Observer::new(observer);
+        self
+    }
+    /** `add_subscriber` adds a [SyncSubscriber] for any [CodexRepository] made from this config afterwards */
+    pub fn add_subscriber(&mut self, subscriber: Arc<dyn SyncSubscriber>) -> &mut Self {
+        self.subscribers.add(subscriber);
+        self
+    }
+    /** `interrupt` starts the cancellation and time limit checks for a network operation */
+    fn interrupt(&self) -> Interrupt {
+        Interrupt::new(&self.cancel, self.timeout_ms.map(Duration::from_millis))
+    }
     /** `fetch_options` retrieves fetch options */
-    fn fetch_options(&self) -> Result<FetchOptions> {
+    fn fetch_options(
+        &self,
+        interrupt: &Interrupt,
+        progress: &Reporter,
+    ) -> Result<FetchOptions<'_>> {
         let mut fo = FetchOptions::new();
-        fo.remote_callbacks(self.callbacks()?);
+        fo.remote_callbacks(self.callbacks(interrupt, progress)?);
+        fo.proxy_options(self.https.proxy_options());
+        let headers = self.https.custom_headers();
+        fo.custom_headers(&headers.iter().map(|h| h.as_str()).collect::<Vec<_>>());
         Ok(fo)
     }
-    /** `callbacks` sets callbacks for calls to git2 that use SSH */
-    fn callbacks(&self) -> Result<RemoteCallbacks> {
+    /** `push_options` retrieves push options using the callbacks (from [Self::callbacks]) */
+    fn push_options<'a>(&self, cb: RemoteCallbacks<'a>) -> PushOptions<'a> {
+        let mut po = PushOptions::new();
+        po.remote_callbacks(cb);
+        po.proxy_options(self.https.proxy_options());
+        let headers = self.https.custom_headers();
+        po.custom_headers(&headers.iter().map(|h| h.as_str()).collect::<Vec<_>>());
+        po
+    }
+    /** `callbacks` sets callbacks for calls to git2 that use SSH or HTTPS, which report progress and abort the call if it is interrupted */
+    fn callbacks(&self, interrupt: &Interrupt, progress: &Reporter) -> Result<RemoteCallbacks<'_>> {
         let mut cb = RemoteCallbacks::new();
         let git_config = git2::Config::open_default()?;
         let mut ch = CredentialHandler::new(git_config);
         let mut try_count: i8 = 0;
         const MAX_TRIES: i8 = 5;
+        let mut https_tried = false;
+        self.https.apply_ca_bundle(&self.remote_url)?;
+        let stop = interrupt.clone();
         cb.credentials(move |url, username, allowed| {
+            if stop.should_stop() {
+                return Err(git2::Error::from_str("operation stopped"));
+            }
+            if https::is_http_url(url) && self.https.has_credentials() {
+                if https_tried {
+                    error!("https credentials rejected for {}", url);
+                    return Err(git2::Error::new(
+                        ErrorCode::Auth,
+                        ErrorClass::Http,
+                        "https credentials rejected",
+                    ));
+                }
+                https_tried = true;
+                if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                    if let Some(cred) = self.https.credential() {
+                        git_trace!("using configured https credential");
+                        return cred;
+                    }
+                }
+                return Err(git2::Error::new(
+                    ErrorCode::Auth,
+                    ErrorClass::Http,
+                    "https remote needs credentials that are not configured",
+                ));
+            }
             if allowed.contains(CredentialType::SSH_MEMORY) {
                 git_trace!("trying ssh memory credential");
-                let username = username.expect("no user name");
+                let username = match username {
+                    Some(u) => u,
+                    None => {
+                        return Err(git2::Error::new(
+                            ErrorCode::Auth,
+                            ErrorClass::Ssh,
+                            "no user name for the ssh key",
+                        ))
+                    }
+                };
                 //                git_trace!("user name is {}, using key option", &username);
                 let cred_res = Cred::ssh_key_from_memory(
                     username,
                     Some(&self.ssh_keys.public),
                     &self.ssh_keys.private,
-                    None,
+                    self.ssh_keys.passphrase.as_deref(),
                 );
                 git_trace!("try to find ssh memory credential");
-                match &cred_res {
-                    Err(e) => {
-                        error!("error found in credential from memory {:?}", e);
-                    }
-                    Ok(_cr) => {
-                    }
+                if let Err(e) = &cred_res {
+                    error!("error found in credential from memory {:?}", e);
                 }
                 return cred_res;
             }
@@ -191,45 +341,78 @@ impl CodexRepoConfig {
             try_count += 1;
             if try_count > MAX_TRIES {
                 error!("too many tries for ssh key");
-                std::panic::panic_any("too many ssh tries".to_string());
+                return Err(git2::Error::new(
+                    ErrorCode::Auth,
+                    ErrorClass::Ssh,
+                    "too many ssh tries",
+                ));
             }
             ch.try_next_credential(url, username, allowed)
         });
+        if self.https.pinned_certificate().is_some() && https::is_http_url(&self.remote_url) {
+            cb.certificate_check(move |cert, host| self.https.check_certificate(cert, host));
+        }
 
         // Print out our transfer progress.
-        if self.verbose {
-            cb.transfer_progress(|stats| {
-                if stats.received_objects() == stats.total_objects() {
-                    git_trace!(
-                        "Resolving deltas {}/{} ",
-                        stats.indexed_deltas(),
-                        stats.total_deltas()
-                    );
-                } else if stats.total_objects() > 0 {
-                    git_trace!(
-                        "Received {}/{} objects ({}) in {} bytes ",
-                        stats.received_objects(),
-                        stats.total_objects(),
-                        stats.indexed_objects(),
-                        stats.received_bytes()
-                    );
-                }
-                true
-            });
-            cb.sideband_progress(|msg| {
-                if msg.len() == 0 {
-                    return true;
-                }
+        let verbose = self.verbose;
+        let stop = interrupt.clone();
+        let reporter = progress.clone();
+        cb.transfer_progress(move |stats| {
+            if stop.should_stop() {
+                return false;
+            }
+            reporter.transfer(&stats);
+            if !verbose {
+                return true;
+            }
+            if stats.received_objects() == stats.total_objects() {
                 git_trace!(
-                    "git: {}",
-                    std::str::from_utf8(msg).unwrap_or_else(|err| {
-                        error!("bad git utf8 message {:?}", &err);
-                        "bad msg"
-                    })
+                    "Resolving deltas {}/{} ",
+                    stats.indexed_deltas(),
+                    stats.total_deltas()
                 );
-                true
+            } else if stats.total_objects() > 0 {
+                git_trace!(
+                    "Received {}/{} objects ({}) in {} bytes ",
+                    stats.received_objects(),
+                    stats.total_objects(),
+                    stats.indexed_objects(),
+                    stats.received_bytes()
+                );
+            }
+            true
+        });
+        let stop = interrupt.clone();
+        let reporter = progress.clone();
+        cb.sideband_progress(move |msg| {
+            if stop.should_stop() {
+                return false;
+            }
+            if msg.is_empty() {
+                return true;
+            }
+            let text = std::str::from_utf8(msg).unwrap_or_else(|err| {
+                error!("bad git utf8 message {:?}", &err);
+                "bad msg"
             });
-        }
+            reporter.message(text);
+            if verbose {
+                git_trace!("git: {}", text);
+            }
+            true
+        });
+        let reporter = progress.clone();
+        cb.pack_progress(move |_stage, current, total| {
+            reporter.objects(ProgressPhase::Packing, current, total, 0);
+        });
+        let reporter = progress.clone();
+        // git2 ignores the result of this callback, so it cannot stop the push
+        cb.push_transfer_progress(move |current, total, bytes| {
+            reporter.objects(ProgressPhase::Pushing, current, total, bytes);
+            if verbose {
+                git_trace!("Sent {}/{} objects in {} bytes", current, total, bytes);
+            }
+        });
         Ok(cb)
     }
 }
@@ -277,15 +460,13 @@ impl fmt::Debug for FetchStatus {
         let mut cc = Vec::<String>::new();
         let confict_msg = if self.has_conflict() {
             if let Some(ix) = &self.index {
-                for conflict in ix.conflicts().expect("bad conflicts") {
-                    if let Ok(c) = conflict {
-                        let p = if let Some(our) = c.our {
-                            std::str::from_utf8(&our.path).expect("bad utf").to_string()
-                        } else {
-                            "?".to_string()
-                        };
-                        cc.push(p);
-                    }
+                for c in ix.conflicts().expect("bad conflicts").flatten() {
+                    let p = if let Some(our) = c.our {
+                        std::str::from_utf8(&our.path).expect("bad utf").to_string()
+                    } else {
+                        "?".to_string()
+                    };
+                    cc.push(p);
                 }
             }
             format!("conflicts [{}]", cc.join(" "))
@@ -327,6 +508,10 @@ pub struct CodexRepository {
     needs_push: bool,
     /// added files
     added: Vec<String>,
+    /// told about what the repository does
+    events: Subscribers,
+    /// commit and push when dropped
+    sync_on_drop: bool,
 }
 impl fmt::Display for CodexRepository {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -348,11 +533,26 @@ impl fmt::Display for CodexRepository {
 }
 impl Drop for CodexRepository {
     fn drop(&mut self) {
+        if !self.sync_on_drop {
+            git_trace!("at end (dropping repo), not syncing");
+            return;
+        }
         git_trace!("at end (dropping repo), committing and pushing repo if required");
-        self.commit_and_push().unwrap_or_else(|err| {
-            error!("drop error: {:?}", &err);
-            panic!("drop error")
-        });
+        match self.commit_and_push() {
+            Ok(()) => {}
+            // the push was stopped on purpose, the commits are kept for the next push
+            Err(CodexGitError::Cancelled) | Err(CodexGitError::TimedOut { .. }) => {
+                error!("drop: push stopped, not pushed")
+            }
+            // offline, behind the remote or conflicted: the changes are kept for the next sync
+            Err(
+                err @ (CodexGitError::Network { .. }
+                | CodexGitError::NonFastForward(_)
+                | CodexGitError::MergeConflict { .. }),
+            ) => error!("drop: not synced: {}", err),
+            // anything else (such as a rejected login or a locked index) cannot be returned from here either
+            Err(err) => error!("drop error: {:?}", &err),
+        }
         // git_trace!("dropping.");
     }
 }
@@ -364,86 +564,152 @@ impl fmt::Debug for CodexRepository {
 impl CodexRepository {
     /// initialise the repository based on the [CodexRepoConfig].
     pub fn new(repo: Repository, config: &CodexRepoConfig) -> Self {
+        let mut events = config.subscribers.clone();
+        if config.journal {
+            events.add(Arc::new(Journal::new(repo.path().join(JOURNAL_FILE))));
+        }
         Self {
             repo,
             config: config.clone(),
             needs_commit: false,
             needs_push: false,
             added: vec![],
+            events,
+            sync_on_drop: true,
         }
     }
-    /// fetches data from the remote and merges if necessary
+    /** `drop_without_sync` drops the repository without the commit and push that dropping it normally does */
+    pub(crate) fn drop_without_sync(mut self) {
+        self.sync_on_drop = false;
+    }
+    /** `journal` reads the journal of [SyncEvent]s (empty if the journal is not kept) */
+    pub fn journal(&self) -> Result<Vec<JournalEntry>> {
+        Journal::read(&self.repo.path().join(JOURNAL_FILE))
+    }
+    /// fetches data from the remote and merges the upstream of the current branch (if it has one) into it, setting `ORIG_HEAD` to the commit before the merge (so what the fetch changed is `ORIG_HEAD` to `HEAD`). With [CodexRepoConfig::set_autostash], changes to tracked files are stashed for the merge and then re-applied; if they then conflict, it fails with [CodexGitError::MergeConflict] and the stash is kept. If the merge itself conflicts, it fails with [CodexGitError::MergeConflict] before the changes are re-applied: they stay in the stash (as stash 0) to [Self::stash_pop] once the merge conflicts are resolved and committed. While they are not, it fails with [CodexGitError::MergeConflict] without fetching.
     pub fn fetch(&mut self) -> Result<()> {
-        let remote_name = "origin";
-        let remote_branch = "main";
+        let branch = self.current_branch()?;
+        pull::check_conflicts(&self.repo.index()?, &self.events)?;
+        let upstream = self.upstream_branch(&branch);
         // let repo = Repository::open(".")?;
-        let mut remote = self.repo.find_remote(remote_name)?;
-        let fetch_commit = pull::do_fetch(
-            &self.repo,
-            &[remote_branch],
-            &mut remote,
-            self.config.callbacks()?,
+        let mut remote = self.repo.find_remote(REMOTE)?;
+        let interrupt = self.config.interrupt();
+        let progress = self.config.observer.reporter();
+        let fetch_commit = self.config.retry.run("fetch", &interrupt, || {
+            progress.enter(ProgressPhase::Connecting);
+            Ok(pull::do_fetch(
+                &self.repo,
+                upstream.as_deref(),
+                &mut remote,
+                self.config.fetch_options(&interrupt, &progress)?,
+                &progress,
+            )?)
+        })?;
+        let fetched = match fetch_commit.map(|commit| commit.id()) {
+            Some(oid) => oid,
+            None => {
+                // a branch that is not on the remote yet only gets the other branches
+                git_trace!("{} has no upstream, not merging", &branch);
+                progress.enter(ProgressPhase::Done);
+                return Ok(());
+            }
+        };
+        drop(remote);
+        let stashed = if self.config.autostash && !self.tracked_changes()?.is_empty() {
+            self.stash_save(Some("autostash before merge"), false)?
+        } else {
+            None
+        };
+        if let Some(head) = self.repo.head().ok().and_then(|h| h.target()) {
+            self.repo
+                .reference("ORIG_HEAD", head, true, "fetch: before merge")?;
+        }
+        let fetch_commit = self.repo.find_annotated_commit(fetched)?;
+        pull::do_merge(&self.repo, &branch, fetch_commit, &progress, &self.events).inspect_err(
+            |_| {
+                if let Some(oid) = stashed {
+                    error!("merge failed, local changes are kept in stash {}", oid);
+                }
+            },
         )?;
-        pull::do_merge(&self.repo, &remote_branch, fetch_commit)?;
+        if stashed.is_some() {
+            if let StashOutcome::Conflicted { paths } = self.stash_pop(0)? {
+                self.events.emit(SyncEvent::Conflict {
+                    paths: paths.clone(),
+                });
+                return Err(CodexGitError::MergeConflict { paths });
+            }
+        }
+        progress.enter(ProgressPhase::Done);
         Ok(())
     }
     /** `commit_and_push` commits changes and pushes them */
     pub fn commit_and_push(&mut self) -> Result<()> {
-        self.commit().context(format!(
-            "error in commit ({} commit)",
-            if self.needs_commit {
-                "needs"
-            } else {
-                "does not need"
-            }
-        ))?;
-        self.push(false).context(format!(
-            "error in push ({} push)",
-            if self.needs_push {
-                "needs"
-            } else {
-                "does not need"
-            }
-        ))?;
+        self.commit()
+            .inspect_err(|e| error!("error in commit ({}): {}", self, e))?;
+        self.push(false)
+            .inspect_err(|e| error!("error in push ({}): {}", self, e))?;
         Ok(())
     }
-    /** `commit` commits any changes to the local repository. */
+    /** `commit` commits any changes to the current branch of the local repository. Unless [AutoAdd::detect] is set, only changes since a call to [Self::add] are committed. It fails with [CodexGitError::MergeConflict] while the index has conflicts. Once the conflicts left by [Self::fetch] are resolved and added, the commit is the merge commit, with the remote commit as its second parent. */
     pub fn commit(&mut self) -> NullResult {
-        if !self.needs_commit {
+        if !self.needs_commit && !self.detect_changes()? {
             git_trace!("no changes, do not need commit");
             return Ok(());
         }
-        // TODO does this help?
+        self.check_on_branch()?;
+        pull::check_conflicts(&self.repo.index()?, &self.events)?;
         git_trace!("adding all from: {:?}", self.config.auto_add);
-        let mut index = self.repo.index().context("cannot get the Index file")?;
+        let mut index = self.repo.index()?;
         let mut paths = vec![];
-        index.add_all(
-            self.config.auto_add.iter(),
-            IndexAddOption::DEFAULT,
-            Some(&mut |path, spec| {
-                paths.push(format!("{:?}", &path));
-                git_trace!(
-                    "adding for commit {:?} for {}",
-                    &path,
-                    std::str::from_utf8(spec).unwrap()
-                );
-                0
-            }),
-        )?;
-        index.write().context("writing index for commit")?;
+        let mut staged = vec![];
+        let workdir = self
+            .repo
+            .workdir()
+            .ok_or_else(|| anyhow!("repository has no working tree"))?
+            .to_path_buf();
+        for path in self.auto_add_dry_run()? {
+            if workdir.join(&path).symlink_metadata().is_ok() {
+                git_trace!("adding for commit {:?}", &path);
+                index.add_path(Path::new(&path))?;
+            } else {
+                git_trace!("removing for commit {:?}", &path);
+                index.remove_path(Path::new(&path))?;
+            }
+            paths.push(format!("{:?}", Path::new(&path)));
+            staged.push(path);
+        }
+        index.write()?;
+        if !staged.is_empty() {
+            self.events.emit(SyncEvent::Staged { paths: staged });
+        }
         //        git_trace!("committing");
         {
             let tree = self.repo.find_tree(self.repo.index()?.write_tree()?)?;
             let our_commit = self.our_commit()?;
-            let _oid = self.write_commit(
-                tree,
-                &format!(
+            let merge_head = match self.repo.state() {
+                RepositoryState::Merge => {
+                    Some(self.repo.find_reference("MERGE_HEAD")?.peel_to_commit()?)
+                }
+                _ => None,
+            };
+            let message = match &merge_head {
+                Some(theirs) => format!("Merge: {} into {}", theirs.id(), our_commit.id()),
+                None => format!(
                     "commit changes {} {}",
                     paths.join(" "),
                     self.added.join(" ")
                 ),
-                &[&our_commit],
-            )?;
+            };
+            let parents: Vec<&Commit<'_>> = std::iter::once(&our_commit)
+                .chain(merge_head.as_ref())
+                .collect();
+            let oid = self.write_commit(tree, &message, &parents)?;
+            self.repo.cleanup_state()?;
+            self.events.emit(SyncEvent::Committed {
+                oid: oid.to_string(),
+                message,
+            });
         }
         self.added.clear();
         self.needs_commit = false;
@@ -451,6 +717,15 @@ impl CodexRepository {
         //  git_trace!("committed");
         Ok(())
     }
+    /** `detect_changes` is whether [AutoAdd::detect] is set and there are files for [Self::commit] to stage (there are none when `HEAD` is detached) */
+    fn detect_changes(&self) -> Result<bool> {
+        if !self.config.auto_add.detect() || self.repo.head_detached()? {
+            return Ok(false);
+        }
+        let found = self.auto_add_dry_run()?;
+        git_trace!("detected changes in {:?}", &found);
+        Ok(!found.is_empty())
+    }
     /** `write_commit` writes out a commit */
     fn write_commit(
         &self,
@@ -458,7 +733,7 @@ impl CodexRepository {
         message: &str,
         parent_commits: &[&Commit<'_>],
     ) -> Result<Oid> {
-        let update_ref = if parent_commits.len() > 0 {
+        let update_ref = if !parent_commits.is_empty() {
             Some("HEAD")
         } else {
             None
@@ -476,10 +751,10 @@ impl CodexRepository {
     }
     /** latest local commit for fetch */
     fn our_commit(&self) -> Result<Commit<'_>> {
-        Ok(self.last_commit()?.ok_or_else(|| (anyhow!("no commit")))?)
+        Ok(self.last_commit()?.ok_or_else(|| anyhow!("no commit"))?)
     }
     /** `last_commit` finds the most recent commit or None */
-    fn last_commit(&self) -> Result<Option<Commit>> {
+    fn last_commit(&self) -> Result<Option<Commit<'_>>> {
         let head = self.repo.head()?.resolve()?.peel(ObjectType::Commit)?;
         Ok(Some(
             head.into_commit().map_err(|_e| anyhow!("not a commit"))?,
@@ -488,34 +763,82 @@ impl CodexRepository {
     /** `add` adds a file to the index */
     pub fn add(&mut self, path: PathBuf) -> NullResult {
         git_trace!("adding {:?}", &path);
+        self.check_on_branch()?;
         self.repo.index()?.add_path(&path)?;
         self.needs_commit = true;
         self.added.push(path.to_string_lossy().to_string());
+        self.events.emit(SyncEvent::Staged {
+            paths: vec![path.to_string_lossy().to_string()],
+        });
         Ok(())
     }
-    /** `push` tries to push any local changes to the remote. */
+    /** `push` tries to push any local changes on the current branch to its upstream, or to a branch of the same name on the remote (which then becomes the upstream). */
     pub fn push(&mut self, force: bool) -> NullResult {
         if !self.needs_push {
             git_trace!("no commits, do not need push");
             return Ok(());
         }
         git_trace!("pushing to remote");
-        let mut remote = self.repo.find_remote("origin")?;
-        let cb = self.config.callbacks()?;
-        remote.connect_auth(Direction::Push, Some(cb), None)?;
-        let mut push_options = PushOptions::new();
-        let cb = self.config.callbacks()?;
-        push_options.remote_callbacks(cb);
+        let branch = self.current_branch()?;
+        let upstream = self.upstream_branch(&branch);
         let force_marker = if force { "+" } else { "" };
         let refspec = format!(
             "{}refs/heads/{}:refs/heads/{}",
-            force_marker, "main", "main"
+            force_marker,
+            &branch,
+            upstream.as_deref().unwrap_or(&branch)
         );
-        remote.push(&[refspec.as_str()], Some(&mut push_options))?;
+        self.push_refspecs(&[refspec])?;
+        if upstream.is_none() {
+            // as `git push -u`; the remote-tracking branch is not made by the push
+            if let Some(tip) = self.repo.head()?.target() {
+                self.repo.reference(
+                    &format!("refs/remotes/{}/{}", REMOTE, &branch),
+                    tip,
+                    true,
+                    "push",
+                )?;
+            }
+            self.set_upstream(&branch, Some(&format!("{}/{}", REMOTE, &branch)))?;
+        }
         self.needs_push = false;
         git_trace!("pushed");
         Ok(())
     }
+    /** `push_refspecs` pushes to the remote, failing with [CodexGitError::NonFastForward] if the remote refuses any of the references, and emits [SyncEvent::Pushed] */
+    pub(crate) fn push_refspecs(&self, refspecs: &[String]) -> NullResult {
+        let mut remote = self.repo.find_remote(REMOTE)?;
+        let interrupt = self.config.interrupt();
+        let progress = self.config.observer.reporter();
+        let updated = self.config.retry.run("push", &interrupt, || {
+            progress.enter(ProgressPhase::Connecting);
+            let rejected = std::cell::RefCell::new(Vec::<String>::new());
+            let updated = std::cell::RefCell::new(Vec::<String>::new());
+            {
+                let mut cb = self.config.callbacks(&interrupt, &progress)?;
+                cb.push_update_reference(|refname, status| {
+                    match status {
+                        Some(msg) => {
+                            error!("push of {} rejected: {}", refname, msg);
+                            rejected.borrow_mut().push(format!("{} ({})", refname, msg));
+                        }
+                        None => updated.borrow_mut().push(refname.to_string()),
+                    }
+                    Ok(())
+                });
+                let mut push_options = self.config.push_options(cb);
+                remote.push(refspecs, Some(&mut push_options))?;
+            }
+            let rejected = rejected.into_inner();
+            if !rejected.is_empty() {
+                return Err(CodexGitError::NonFastForward(rejected.join(", ")));
+            }
+            Ok(updated.into_inner())
+        })?;
+        progress.enter(ProgressPhase::Done);
+        self.events.emit(SyncEvent::Pushed { refs: updated });
+        Ok(())
+    }
 }
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
@@ -0,0 +1,134 @@
+/*! Reporting the progress of clone, fetch, checkout and push, for progress bars. */
+use git2::build::CheckoutBuilder;
+use git2::Progress;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/** A `ProgressPhase` is a stage of a network operation. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressPhase {
+    /// connecting to the remote (and authenticating)
+    Connecting,
+    /// receiving objects from the remote
+    Receiving,
+    /// resolving the deltas in the received objects
+    ResolvingDeltas,
+    /// writing files into the working tree
+    CheckingOut,
+    /// building the pack of objects to push
+    Packing,
+    /// sending objects to the remote
+    Pushing,
+    /// the operation has finished
+    Done,
+}
+
+/** A `ProgressObserver` is told about the progress of the network operations of a [crate::CodexRepoConfig] and the [crate::CodexRepository] made from it. All the methods do nothing by default. The observer is called from git2 callbacks, so it should return quickly. */
+pub trait ProgressObserver: Send + Sync {
+    /** `phase` is called when an operation moves to a new phase */
+    fn phase(&self, _phase: ProgressPhase) {}
+    /** `objects` is called with the count of objects (or, for checkout, files) done out of the total so far in a phase, and the bytes transferred */
+    fn objects(&self, _phase: ProgressPhase, _current: usize, _total: usize, _bytes: usize) {}
+    /** `message` is called with each progress message from the remote (the `remote:` lines of git) */
+    fn message(&self, _text: &str) {}
+}
+
+/** An `Observer` holds the [ProgressObserver] (if any) in the config. */
+#[derive(Clone, Default)]
+pub(crate) struct Observer(Option<Arc<dyn ProgressObserver>>);
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            if self.0.is_some() {
+                "(observer)"
+            } else {
+                "(no observer)"
+            }
+        )
+    }
+}
+impl Observer {
+    pub(crate) fn new(observer: Arc<dyn ProgressObserver>) -> Self {
+        Self(Some(observer))
+    }
+    /** `reporter` starts reporting for one operation */
+    pub(crate) fn reporter(&self) -> Reporter {
+        Reporter {
+            observer: self.0.clone(),
+            phase: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+/** A `Reporter` passes the progress of one operation to the [ProgressObserver], telling it about each change of phase once. */
+#[derive(Clone)]
+pub(crate) struct Reporter {
+    observer: Option<Arc<dyn ProgressObserver>>,
+    phase: Arc<Mutex<Option<ProgressPhase>>>,
+}
+impl Reporter {
+    /** `enter` moves to a phase */
+    pub(crate) fn enter(&self, phase: ProgressPhase) {
+        let observer = match &self.observer {
+            Some(o) => o,
+            None => return,
+        };
+        let mut current = match self.phase.lock() {
+            Ok(c) => c,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if *current != Some(phase) {
+            *current = Some(phase);
+            observer.phase(phase);
+        }
+    }
+    /** `objects` reports counts in a phase */
+    pub(crate) fn objects(&self, phase: ProgressPhase, current: usize, total: usize, bytes: usize) {
+        if let Some(observer) = &self.observer {
+            self.enter(phase);
+            observer.objects(phase, current, total, bytes);
+        }
+    }
+    /** `transfer` reports git2 transfer progress as receiving or resolving deltas */
+    pub(crate) fn transfer(&self, stats: &Progress<'_>) {
+        if stats.total_objects() > 0 && stats.received_objects() == stats.total_objects() {
+            if stats.total_deltas() > 0 {
+                self.objects(
+                    ProgressPhase::ResolvingDeltas,
+                    stats.indexed_deltas(),
+                    stats.total_deltas(),
+                    stats.received_bytes(),
+                );
+            }
+        } else {
+            self.objects(
+                ProgressPhase::Receiving,
+                stats.received_objects(),
+                stats.total_objects(),
+                stats.received_bytes(),
+            );
+        }
+    }
+    /** `message` reports a progress message from the remote */
+    pub(crate) fn message(&self, text: &str) {
+        if let Some(observer) = &self.observer {
+            observer.message(text);
+        }
+    }
+    /** `checkout` is a [CheckoutBuilder] that reports its progress */
+    pub(crate) fn checkout<'cb>(&self) -> CheckoutBuilder<'cb> {
+        let mut builder = CheckoutBuilder::new();
+        if self.observer.is_some() {
+            let reporter = self.clone();
+            builder.progress(move |_path, current, total| {
+                reporter.objects(ProgressPhase::CheckingOut, current, total, 0)
+            });
+        }
+        builder
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
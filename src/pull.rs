@@ -4,8 +4,11 @@
  *
  * Original written by the libgit2 contributors.
 */
+use crate::events::Subscribers;
+use crate::progress::Reporter;
+use crate::{CodexGitError, MergeKind, SyncEvent};
 use ansi_term::Colour::*;
-use git2::{RemoteCallbacks, Repository};
+use git2::{FetchOptions, Repository};
 use log::trace;
 use std::str;
 
@@ -19,22 +22,23 @@ macro_rules! git_pull_trace {
 /// performs a `git2` fetch.
 pub fn do_fetch<'a>(
     repo: &'a git2::Repository,
-    refs: &[&str],
-    remote: &'a mut git2::Remote,
-    cb: RemoteCallbacks,
-) -> Result<git2::AnnotatedCommit<'a>, git2::Error> {
+    upstream: Option<&str>,
+    remote: &mut git2::Remote,
+    mut fo: FetchOptions,
+    progress: &Reporter,
+) -> Result<Option<git2::AnnotatedCommit<'a>>, git2::Error> {
     git_pull_trace!("fetching...");
-    let mut fo = git2::FetchOptions::new();
-    fo.remote_callbacks(cb);
     // Always fetch all tags.
     // Perform a download and also update tips
     fo.download_tags(git2::AutotagOption::All);
     git_pull_trace!("Fetching {} for repo", remote.name().unwrap());
-    remote.fetch(refs, Some(&mut fo), None)?;
+    // all the branches, by the remote's refspecs
+    remote.fetch::<&str>(&[], Some(&mut fo), None)?;
 
     // If there are local objects (we got a thin pack), then tell the user
     // how many objects we saved from having to cross the network.
     let stats = remote.stats();
+    progress.transfer(&stats);
     if stats.local_objects() > 0 {
         git_pull_trace!(
             "\rReceived {}/{} objects in {} bytes (used {} local \
@@ -53,10 +57,18 @@ pub fn do_fetch<'a>(
         );
     }
 
-    let fetch_head = repo.find_reference("FETCH_HEAD")?;
-    let commit = repo.reference_to_annotated_commit(&fetch_head)?;
+    let upstream = match upstream {
+        Some(upstream) => upstream,
+        None => return Ok(None),
+    };
+    let tracking = repo.find_reference(&format!(
+        "refs/remotes/{}/{}",
+        remote.name().unwrap_or("origin"),
+        upstream
+    ))?;
+    let commit = repo.reference_to_annotated_commit(&tracking)?;
     git_pull_trace!("fetched {}", commit.refname().unwrap_or("[not valid]"));
-    Ok(commit)
+    Ok(Some(commit))
 }
 
 /// performs a `git2` fast forward
@@ -64,6 +76,7 @@ fn fast_forward(
     repo: &Repository,
     lb: &mut git2::Reference,
     rc: &git2::AnnotatedCommit,
+    progress: &Reporter,
 ) -> Result<(), git2::Error> {
     git_pull_trace!("fast forwarding...");
     let name = match lb.name() {
@@ -75,7 +88,8 @@ fn fast_forward(
     lb.set_target(rc.id(), &msg)?;
     repo.set_head(&name)?;
     repo.checkout_head(Some(
-        git2::build::CheckoutBuilder::default()
+        progress
+            .checkout()
             // For some reason the force is required to make the working directory actually get updated
             // I suspect we should be adding some logic to handle dirty working directory states
             // but this is just an example so maybe not.
@@ -85,12 +99,14 @@ fn fast_forward(
 }
 
 /** performs a `git2` 'normal' merge (not fast forward). Conficts are detected
-but not handled. */
+and checked out into the working tree but not handled. */
 fn normal_merge(
     repo: &Repository,
     local: &git2::AnnotatedCommit,
     remote: &git2::AnnotatedCommit,
-) -> Result<(), git2::Error> {
+    progress: &Reporter,
+    events: &Subscribers,
+) -> crate::Result<()> {
     git_pull_trace!("merging normally...");
     let local_tree = repo.find_commit(local.id())?.tree()?;
     let remote_tree = repo.find_commit(remote.id())?.tree()?;
@@ -101,8 +117,10 @@ fn normal_merge(
 
     if idx.has_conflicts() {
         git_pull_trace!("Merge conficts detected...");
-        repo.checkout_index(Some(&mut idx), None)?;
-        return Ok(());
+        repo.checkout_index(Some(&mut idx), Some(&mut progress.checkout()))?;
+        // as git does, so that committing the resolution makes the merge commit
+        repo.reference("MERGE_HEAD", remote.id(), true, "merge with conflicts")?;
+        return check_conflicts(&idx, events);
     }
     let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
     // now create the merge commit
@@ -123,16 +141,46 @@ fn normal_merge(
         git_pull_trace!("merge tree has {:?}", &e.name().unwrap_or("[not valid]"));
     }
     // Set working tree to match head.
-    repo.checkout_head(None)?;
+    repo.checkout_head(Some(&mut progress.checkout()))?;
+    events.emit(SyncEvent::Merged {
+        kind: MergeKind::Normal,
+        oid: merge_commit.to_string(),
+    });
     Ok(())
 }
 
-/// performs a `git2` merge after a fetch.
+/// lists the paths with conflicts in an index.
+pub fn conflict_paths(index: &git2::Index) -> Result<Vec<String>, git2::Error> {
+    let mut paths = vec![];
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+            paths.push(String::from_utf8_lossy(&entry.path).to_string());
+        }
+    }
+    Ok(paths)
+}
+
+/// fails with [CodexGitError::MergeConflict] (emitting [SyncEvent::Conflict]) if an index has conflicts.
+pub fn check_conflicts(index: &git2::Index, events: &Subscribers) -> crate::Result<()> {
+    if !index.has_conflicts() {
+        return Ok(());
+    }
+    let paths = conflict_paths(index)?;
+    events.emit(SyncEvent::Conflict {
+        paths: paths.clone(),
+    });
+    Err(CodexGitError::MergeConflict { paths })
+}
+
+/// performs a `git2` merge into a local branch after a fetch.
 pub fn do_merge<'a>(
     repo: &'a Repository,
-    remote_branch: &str,
+    local_branch: &str,
     fetch_commit: git2::AnnotatedCommit<'a>,
-) -> Result<(), git2::Error> {
+    progress: &Reporter,
+    events: &Subscribers,
+) -> crate::Result<()> {
     git_pull_trace!("doing merge...");
     // 1. do a merge analysis
     let analysis = repo.merge_analysis(&[&fetch_commit])?;
@@ -141,10 +189,10 @@ pub fn do_merge<'a>(
     if analysis.0.is_fast_forward() {
         git_pull_trace!("Doing a fast forward");
         // do a fast forward
-        let refname = format!("refs/heads/{}", remote_branch);
+        let refname = format!("refs/heads/{}", local_branch);
         match repo.find_reference(&refname) {
             Ok(mut r) => {
-                fast_forward(repo, &mut r, &fetch_commit)?;
+                fast_forward(repo, &mut r, &fetch_commit, progress)?;
             }
             Err(_) => {
                 // The branch doesn't exist so just set the reference to the
@@ -155,32 +203,44 @@ pub fn do_merge<'a>(
                     &refname,
                     fetch_commit.id(),
                     true,
-                    &format!("Setting {} to {}", remote_branch, fetch_commit.id()),
+                    &format!("Setting {} to {}", local_branch, fetch_commit.id()),
                 )?;
                 repo.set_head(&refname)?;
                 repo.checkout_head(Some(
-                    git2::build::CheckoutBuilder::default()
+                    progress
+                        .checkout()
                         .allow_conflicts(true)
                         .conflict_style_merge(true)
                         .force(),
                 ))?;
             }
         };
+        events.emit(SyncEvent::Merged {
+            kind: MergeKind::FastForward,
+            oid: fetch_commit.id().to_string(),
+        });
     } else if analysis.0.is_normal() {
         // do a normal merge
         let head_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
-        normal_merge(&repo, &head_commit, &fetch_commit)?;
+        normal_merge(repo, &head_commit, &fetch_commit, progress, events)?;
     } else {
         git_pull_trace!("Nothing to do...");
+        events.emit(SyncEvent::Merged {
+            kind: MergeKind::UpToDate,
+            oid: repo
+                .head()
+                .ok()
+                .and_then(|h| h.target())
+                .map(|oid| oid.to_string())
+                .unwrap_or_default(),
+        });
     }
     Ok(())
 }
 
-
 /*
  * For git2 code: To the extent possible under law, the author(s) have dedicated all copyright and related and neighboring rights to the original software to the public domain worldwide. This software is distributed without any warranty. See <http://creativecommons.org/publicdomain/zero/1.0/>.
  */
 /* For the rest of the code (if applicable). This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
-
@@ -0,0 +1,105 @@
+/*! The reflog: where `HEAD` and the branches have been, for recovering lost commits. */
+use crate::history::to_datetime;
+use crate::{CodexRepository, NullResult, ResetMode, Result, User};
+use ansi_term::Colour::*;
+use chrono::{DateTime, FixedOffset};
+use getset::{CopyGetters, Getters};
+use git2::Oid;
+use log::trace;
+
+/// tracing macro
+macro_rules! reflog_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Green).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `ReflogEntry` is one move of `HEAD` or a branch. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct ReflogEntry {
+    /// the full name of the reference, such as `HEAD` or `refs/heads/main`
+    #[getset(get = "pub")]
+    reference: String,
+    /// position in the reflog, 0 is the most recent (as in `HEAD@{0}`)
+    #[getset(get_copy = "pub")]
+    index: usize,
+    /// where the reference was before (zero if it did not exist)
+    #[getset(get_copy = "pub")]
+    old_oid: Oid,
+    /// where the reference was moved to
+    #[getset(get_copy = "pub")]
+    new_oid: Oid,
+    /// why it moved, such as `commit: ...` or `reset: moving to ...`
+    #[getset(get = "pub")]
+    message: Option<String>,
+    /// who moved it
+    #[getset(get = "pub")]
+    committer: User,
+    /// when it moved
+    #[getset(get_copy = "pub")]
+    time: DateTime<FixedOffset>,
+}
+
+/** `Recovery` is what [CodexRepository::recover] does with the commit of a [ReflogEntry]. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recovery {
+    /// make a new branch at the commit, leaving the current branch alone
+    Branch(String),
+    /// reset the current branch to the commit (as [CodexRepository::reset_to])
+    Reset(ResetMode),
+}
+
+impl CodexRepository {
+    /** `reflog` lists the moves of `HEAD` or a branch (given as `main` or `refs/heads/main`), most recent first */
+    pub fn reflog(&self, reference: &str) -> Result<Vec<ReflogEntry>> {
+        let reference = if reference == "HEAD" || reference.starts_with("refs/") {
+            reference.to_string()
+        } else {
+            format!("refs/heads/{}", reference)
+        };
+        let reflog = self.repo.reflog(&reference)?;
+        reflog
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let committer = entry.committer();
+                Ok(ReflogEntry {
+                    reference: reference.clone(),
+                    index,
+                    old_oid: entry.id_old(),
+                    new_oid: entry.id_new(),
+                    message: entry.message().map(|m| m.to_string()),
+                    committer: User::new(
+                        committer.name().unwrap_or(""),
+                        committer.email().unwrap_or(""),
+                    ),
+                    time: to_datetime(committer.when())?,
+                })
+            })
+            .collect()
+    }
+    /** `recover` brings back the commit that a reflog entry moved to, on a new branch or by resetting the current branch to it */
+    pub fn recover(&mut self, entry: &ReflogEntry, how: Recovery) -> NullResult {
+        reflog_trace!(
+            "recovering {} ({:?}) by {:?}",
+            entry.new_oid,
+            entry.message,
+            how
+        );
+        match how {
+            Recovery::Branch(name) => {
+                let commit = self.repo.find_commit(entry.new_oid)?;
+                self.repo.branch(&name, &commit, false)?;
+                Ok(())
+            }
+            Recovery::Reset(mode) => {
+                self.check_on_branch()?;
+                self.reset_commit(entry.new_oid, mode.into())
+            }
+        }
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,165 @@
+/*! Parsing of remote repository URLs. */
+use crate::{CodexGitError, ConfigProblem, Result};
+use getset::{CopyGetters, Getters};
+
+/** `UrlKind` is the form a remote URL is written in. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlKind {
+    /// `ssh://[user@]host[:port]/path`
+    Ssh,
+    /// scp-like `[user@]host:path`
+    Scp,
+    /// `http://` or `https://`
+    Http,
+    /// `git://`
+    Git,
+    /// `file://`
+    File,
+    /// a path on the local file system
+    Local,
+}
+
+/** A `RemoteUrl` is a parsed URL for a remote repository. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct RemoteUrl {
+    /// the form of the URL
+    #[getset(get_copy = "pub")]
+    kind: UrlKind,
+    /// user name, if any
+    #[getset(get = "pub")]
+    user: Option<String>,
+    /// host name, empty for local and `file://` URLs
+    #[getset(get = "pub")]
+    host: String,
+    /// port, if any
+    #[getset(get_copy = "pub")]
+    port: Option<u16>,
+    /// path of the repository on the host
+    #[getset(get = "pub")]
+    path: String,
+}
+impl RemoteUrl {
+    /** `parse` parses ssh://, scp-like, http(s)://, git://, file:// URLs and local paths. */
+    pub fn parse(url: &str) -> Result<Self> {
+        let url = url.trim();
+        if url.is_empty() {
+            return Err(bad_url(url, "empty URL"));
+        }
+        if let Some((scheme, rest)) = url.split_once("://") {
+            let kind = match scheme.to_ascii_lowercase().as_str() {
+                "ssh" | "git+ssh" | "ssh+git" => UrlKind::Ssh,
+                "http" | "https" => UrlKind::Http,
+                "git" => UrlKind::Git,
+                "file" => UrlKind::File,
+                _ => return Err(bad_url(url, "unknown scheme")),
+            };
+            if kind == UrlKind::File {
+                return Ok(Self::local(kind, rest));
+            }
+            let (authority, path) = match rest.find('/') {
+                Some(i) => (&rest[..i], &rest[i..]),
+                None => (rest, ""),
+            };
+            let (user, host_port) = split_user(authority);
+            // an IPv6 address is in brackets and contains colons
+            let port_colon = match host_port.rfind(']') {
+                Some(i) => host_port[i..].find(':').map(|j| i + j),
+                None => host_port.rfind(':'),
+            };
+            let (host, port) = match port_colon {
+                Some(i) => {
+                    let port = host_port[i + 1..]
+                        .parse::<u16>()
+                        .map_err(|_| bad_url(url, "bad port"))?;
+                    (&host_port[..i], Some(port))
+                }
+                None => (host_port, None),
+            };
+            if host.is_empty() {
+                return Err(bad_url(url, "no host"));
+            }
+            return Ok(Self {
+                kind,
+                user,
+                host: host.to_string(),
+                port,
+                path: path.to_string(),
+            });
+        }
+        if let Some((authority, path)) = scp_parts(url) {
+            let (user, host) = split_user(authority);
+            if host.is_empty() {
+                return Err(bad_url(url, "no host"));
+            }
+            return Ok(Self {
+                kind: UrlKind::Scp,
+                user,
+                host: host.to_string(),
+                port: None,
+                path: path.to_string(),
+            });
+        }
+        Ok(Self::local(UrlKind::Local, url))
+    }
+    /** `local` makes a [RemoteUrl] for a path on this machine */
+    fn local(kind: UrlKind, path: &str) -> Self {
+        Self {
+            kind,
+            user: None,
+            host: String::new(),
+            port: None,
+            path: path.to_string(),
+        }
+    }
+    /** `repo_name` is the last part of the path without any `.git` suffix */
+    pub fn repo_name(&self) -> Result<String> {
+        let last = self
+            .path
+            .split(['/', '\\'])
+            .rfind(|s| !s.is_empty())
+            .unwrap_or("");
+        let name = last.strip_suffix(".git").unwrap_or(last);
+        check_dir_name(name)?;
+        Ok(name.to_string())
+    }
+}
+/** `check_dir_name` rejects names that cannot be used as the directory of a repository */
+pub(crate) fn check_dir_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(CodexGitError::invalid_config(ConfigProblem::BadRepoName(
+            name.to_string(),
+        )));
+    }
+    Ok(())
+}
+/** `split_user` splits `user@host` */
+fn split_user(authority: &str) -> (Option<String>, &str) {
+    match authority.rsplit_once('@') {
+        Some((u, h)) => (Some(u.to_string()), h),
+        None => (None, authority),
+    }
+}
+/** `scp_parts` splits a scp-like URL into host and path. As for git, it is only scp-like if there is a colon before any slash, and a single letter before the colon is a Windows drive. */
+fn scp_parts(url: &str) -> Option<(&str, &str)> {
+    let colon = url.find(':')?;
+    if let Some(slash) = url.find(['/', '\\']) {
+        if slash < colon {
+            return None;
+        }
+    }
+    let authority = &url[..colon];
+    if authority.len() == 1 && authority.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((authority, &url[colon + 1..]))
+}
+/** `bad_url` is the error for an unparsable URL */
+fn bad_url(url: &str, why: &str) -> CodexGitError {
+    CodexGitError::invalid_config(ConfigProblem::BadRemoteUrl {
+        url: url.to_string(),
+        reason: why.to_string(),
+    })
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,121 @@
+/*! Retrying the network phases of clone, fetch and push when they fail in a way that may be temporary. */
+use crate::cancel::Interrupt;
+use crate::Result;
+use ansi_term::Colour::*;
+use getset::{CopyGetters, Setters};
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant, SystemTime};
+
+/// tracing macro
+macro_rules! retry_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Yellow).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `RetryPolicy` says how often, and how long after, a network operation is tried again after a retryable error (see [crate::CodexGitError::is_retryable]). Authentication failures and rejected pushes are never retried. The default is a single attempt. */
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters, Setters, Deserialize, Serialize)]
+#[getset(get_copy = "pub", set = "pub")]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// number of attempts, including the first (0 is treated as 1)
+    max_attempts: u32,
+    /// delay before the second attempt, in milliseconds
+    initial_delay_ms: u64,
+    /// each delay is the previous one times this
+    multiplier: f64,
+    /// upper limit for a single delay, in milliseconds
+    max_delay_ms: u64,
+    /// fraction (0 to 1) of each delay that is random, so that clients do not all retry together
+    jitter: f64,
+    /// give up rather than start a retry this long (in milliseconds) after the first attempt started
+    deadline_ms: Option<u64>,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_delay_ms: 500,
+            multiplier: 2.0,
+            max_delay_ms: 30_000,
+            jitter: 0.5,
+            deadline_ms: None,
+        }
+    }
+}
+impl RetryPolicy {
+    /** `new` is a policy of `max_attempts` attempts with the default backoff */
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+    /** `delay` is the delay (before jitter) after the failure of attempt `attempt` (counting from 1) */
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.saturating_sub(1) as i32);
+        let ms = (self.initial_delay_ms as f64 * factor).min(self.max_delay_ms as f64);
+        Duration::from_millis(ms as u64)
+    }
+    /** `jittered` reduces a delay by a random part of the jitter fraction */
+    fn jittered(&self, delay: Duration) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        let mut hasher = RandomState::new().build_hasher();
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(now.as_nanos());
+        }
+        let random = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+        delay.mul_f64(1.0 - jitter * random)
+    }
+    /** `run` calls `op` until it succeeds, fails with an error that is not retryable, is interrupted, or the attempts or time run out */
+    pub(crate) fn run<T>(
+        &self,
+        what: &str,
+        interrupt: &Interrupt,
+        mut op: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let deadline = self.deadline_ms.map(|ms| start + Duration::from_millis(ms));
+        let mut attempt = 1;
+        loop {
+            interrupt.check()?;
+            let err = match op() {
+                Ok(value) => return Ok(value),
+                Err(err) => interrupt.explain(err),
+            };
+            if !err.is_retryable() || attempt >= self.max_attempts {
+                return Err(err);
+            }
+            let delay = self.jittered(self.delay(attempt));
+            let out_of_time = deadline.is_some_and(|d| Instant::now() + delay > d)
+                || interrupt.remaining().is_some_and(|r| delay >= r);
+            if out_of_time {
+                error!("{} failed, no time to retry: {}", what, &err);
+                return Err(err);
+            }
+            retry_trace!(
+                "{} failed (attempt {} of {}), retrying in {:?}: {}",
+                what,
+                attempt,
+                self.max_attempts,
+                delay,
+                &err
+            );
+            interrupt.sleep(delay)?;
+            attempt += 1;
+        }
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
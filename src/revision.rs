@@ -0,0 +1,127 @@
+/*! Reading files as they were at any revision, and restoring them into the working tree. */
+use crate::{CodexGitError, CodexRepository, NullResult, Result};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use git2::{ObjectType, Oid};
+use log::trace;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// tracing macro
+macro_rules! revision_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Green).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+impl CodexRepository {
+    /** `read_at` is the contents of a file (relative to the top of the working tree) at a revision, such as `HEAD~2`, a branch, a tag or an OID. */
+    pub fn read_at(&self, rev: &str, path: &Path) -> Result<Vec<u8>> {
+        let blob = self.repo.find_blob(self.blob_at(rev, path)?.0)?;
+        Ok(blob.content().to_vec())
+    }
+    /** `copy_at` is [Self::read_at] writing the contents to `out` (such as a file), returning the number of bytes written. Objects that the object database can stream (loose objects) are copied a piece at a time without being loaded whole; others (such as packed objects) are read as a blob first. */
+    pub fn copy_at(&self, rev: &str, path: &Path, out: &mut dyn Write) -> Result<u64> {
+        let (oid, _) = self.blob_at(rev, path)?;
+        let odb = self.repo.odb()?;
+        let copied = match odb.reader(oid) {
+            Ok((reader, size, _)) => {
+                revision_trace!("streaming {} ({} bytes)", oid, size);
+                // git2's reader does not report the end of the object, so stop at its size
+                let copied = std::io::copy(&mut reader.take(size as u64), out)?;
+                if copied != size as u64 {
+                    return Err(CodexGitError::Other(anyhow!(
+                        "object {} ended after {} of {} bytes",
+                        oid,
+                        copied,
+                        size
+                    )));
+                }
+                copied
+            }
+            Err(e) => {
+                revision_trace!("cannot stream {} ({}), reading it whole", oid, e.message());
+                let blob = self.repo.find_blob(oid)?;
+                out.write_all(blob.content())?;
+                blob.content().len() as u64
+            }
+        };
+        Ok(copied)
+    }
+    /** `restore` writes a file as it was at a revision into the working tree, as a symbolic link or executable if it was one, and adds it (as [Self::add]) so that the next commit includes it. */
+    pub fn restore(&mut self, path: &Path, rev: &str) -> NullResult {
+        revision_trace!("restoring {:?} from {}", path, rev);
+        let (oid, mode) = self.blob_at(rev, path)?;
+        let contents = self.repo.find_blob(oid)?.content().to_vec();
+        let workdir = self
+            .repo
+            .workdir()
+            .ok_or_else(|| anyhow!("repository has no working tree"))?;
+        let target = workdir.join(path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // replace a link rather than writing through it
+        if target
+            .symlink_metadata()
+            .is_ok_and(|m| m.file_type().is_symlink() || mode == LINK_MODE)
+        {
+            std::fs::remove_file(&target)?;
+        }
+        match mode {
+            LINK_MODE => write_link(&contents, &target)?,
+            _ => {
+                std::fs::write(&target, contents)?;
+                set_executable(&target, mode == EXECUTABLE_MODE)?
+            }
+        }
+        self.add(path.to_path_buf())
+    }
+    /** `blob_at` finds the blob for a path at a revision, and its file mode */
+    fn blob_at(&self, rev: &str, path: &Path) -> Result<(Oid, i32)> {
+        let tree = self.repo.revparse_single(rev)?.peel_to_tree()?;
+        let entry = tree.get_path(path)?;
+        if entry.kind() != Some(ObjectType::Blob) {
+            return Err(CodexGitError::Other(anyhow!(
+                "{:?} is not a file at {}",
+                path,
+                rev
+            )));
+        }
+        Ok((entry.id(), entry.filemode()))
+    }
+}
+/// the git file mode of a symbolic link
+const LINK_MODE: i32 = 0o120000;
+/// the git file mode of an executable file
+const EXECUTABLE_MODE: i32 = 0o100755;
+#[cfg(unix)]
+fn write_link(contents: &[u8], to: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(contents), to)
+}
+#[cfg(not(unix))]
+fn write_link(contents: &[u8], to: &Path) -> std::io::Result<()> {
+    // as git does without symbolic links, the file holds the link target
+    std::fs::write(to, contents)
+}
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(if executable {
+        mode | ((mode & 0o444) >> 2)
+    } else {
+        mode & !0o111
+    });
+    std::fs::set_permissions(path, permissions)
+}
+#[cfg(not(unix))]
+fn set_executable(_path: &Path, _executable: bool) -> std::io::Result<()> {
+    Ok(())
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,111 @@
+/*! Stashing local changes, so that they survive a merge. */
+use crate::pull::conflict_paths;
+use crate::{CodexRepository, NullResult, Result};
+use ansi_term::Colour::*;
+use getset::{CopyGetters, Getters};
+use git2::{ErrorCode, Oid, Signature, StashApplyOptions, StashFlags};
+use log::trace;
+
+/// tracing macro
+macro_rules! stash_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Yellow).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `StashEntry` is a set of stashed changes. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct StashEntry {
+    /// position in the stash list, 0 is the most recent
+    #[getset(get_copy = "pub")]
+    index: usize,
+    /// the stash message
+    #[getset(get = "pub")]
+    message: String,
+    /// the stash commit
+    #[getset(get_copy = "pub")]
+    oid: Oid,
+}
+
+/** `StashOutcome` is the result of re-applying a stash. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StashOutcome {
+    /// the changes are back in the working tree
+    Applied,
+    /// the changes are back, but these paths conflict (they have conflict markers and are conflicted in the index), and the stash is kept
+    Conflicted { paths: Vec<String> },
+}
+
+impl CodexRepository {
+    /** `stash_save` stashes the changes to tracked files (and the untracked files too if `include_untracked`), leaving the working tree as `HEAD`. It is `None` if there is nothing to stash. */
+    pub fn stash_save(
+        &mut self,
+        message: Option<&str>,
+        include_untracked: bool,
+    ) -> Result<Option<Oid>> {
+        stash_trace!("stashing ({:?}, untracked {})", message, include_untracked);
+        let stasher = Signature::now(&self.config.user.name, &self.config.user.email)?;
+        let mut flags = StashFlags::DEFAULT;
+        if include_untracked {
+            flags |= StashFlags::INCLUDE_UNTRACKED;
+        }
+        match self.repo.stash_save2(&stasher, message, Some(flags)) {
+            Ok(oid) => {
+                self.needs_commit = false;
+                Ok(Some(oid))
+            }
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    /** `stashes` lists the stashes, most recent first */
+    pub fn stashes(&mut self) -> Result<Vec<StashEntry>> {
+        let mut found = vec![];
+        self.repo.stash_foreach(|index, message, oid| {
+            found.push(StashEntry {
+                index,
+                message: message.to_string(),
+                oid: *oid,
+            });
+            true
+        })?;
+        Ok(found)
+    }
+    /** `stash_apply` re-applies a stash (0 is the most recent) to the working tree, keeping it in the list */
+    pub fn stash_apply(&mut self, index: usize) -> Result<StashOutcome> {
+        stash_trace!("applying stash {}", index);
+        self.repo
+            .stash_apply(index, Some(&mut StashApplyOptions::new()))?;
+        self.after_unstash()
+    }
+    /** `stash_pop` re-applies a stash (0 is the most recent) and then drops it, unless there are conflicts */
+    pub fn stash_pop(&mut self, index: usize) -> Result<StashOutcome> {
+        stash_trace!("popping stash {}", index);
+        let outcome = self.stash_apply(index)?;
+        if outcome == StashOutcome::Applied {
+            self.stash_drop(index)?;
+        }
+        Ok(outcome)
+    }
+    /** `stash_drop` deletes a stash (0 is the most recent) */
+    pub fn stash_drop(&mut self, index: usize) -> NullResult {
+        stash_trace!("dropping stash {}", index);
+        self.repo.stash_drop(index)?;
+        Ok(())
+    }
+    /** `after_unstash` finds any conflicts left by applying a stash, and if there are none notes that there are changes to commit (conflicts must be resolved first) */
+    fn after_unstash(&mut self) -> Result<StashOutcome> {
+        let paths = conflict_paths(&self.repo.index()?)?;
+        stash_trace!("unstashed, conflicts {:?}", &paths);
+        Ok(if paths.is_empty() {
+            self.needs_commit = true;
+            StashOutcome::Applied
+        } else {
+            StashOutcome::Conflicted { paths }
+        })
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
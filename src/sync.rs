@@ -0,0 +1,265 @@
+/*! Syncing a [CodexRepository] in the background: fetching now and then, and pushing soon after changes are committed. */
+use crate::{CancelToken, CodexGitError, CodexRepository, NullResult, Result, RetryPolicy};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use getset::{CopyGetters, Setters};
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// tracing macro
+macro_rules! sync_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Green).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `SyncOptions` controls a [SyncHandle]. */
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters, Setters, Serialize, Deserialize)]
+#[getset(get_copy = "pub", set = "pub")]
+#[serde(default)]
+pub struct SyncOptions {
+    /// time between syncs, in milliseconds
+    fetch_interval_ms: u64,
+    /// how long after the last change is committed (in milliseconds) to sync, so that several changes go in one push; each change pushes the sync back
+    push_debounce_ms: u64,
+    /// the longest (in milliseconds) changes keep pushing the sync back after the first unpushed one, none for no limit
+    push_max_delay_ms: Option<u64>,
+    /// how long to wait before trying again while the remote cannot be reached (only the delays are used, the attempts are not limited)
+    offline_backoff: RetryPolicy,
+}
+impl Default for SyncOptions {
+    fn default() -> Self {
+        let mut offline_backoff = RetryPolicy::default();
+        offline_backoff
+            .set_initial_delay_ms(5_000)
+            .set_max_delay_ms(300_000);
+        Self {
+            fetch_interval_ms: 60_000,
+            push_debounce_ms: 5_000,
+            push_max_delay_ms: Some(60_000),
+            offline_backoff,
+        }
+    }
+}
+
+/** `SyncState` is what a [SyncHandle] is doing. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncState {
+    /// waiting for the next sync
+    Idle,
+    /// committing, fetching and pushing
+    Syncing,
+    /// the last sync could not reach the remote, it is tried again after a backoff
+    Offline,
+    /// the last sync could not merge the remote changes, leaving conflicts in these paths; resolve them through [SyncHandle::run] (writing the files, then [CodexRepository::add] and [CodexRepository::commit]) and the next sync pushes the merge
+    Conflict { paths: Vec<String> },
+}
+
+/** `Command` is what the sync thread is told */
+enum Command {
+    /// sync as soon as possible
+    SyncNow,
+    /// do something with the repository
+    Run(Box<dyn FnOnce(&mut CodexRepository) + Send>),
+    /// stop syncing
+    Stop,
+}
+
+/** A `SyncHandle` is a [CodexRepository] owned by a background thread that syncs it (commits, fetches and merges, then pushes) every [SyncOptions::fetch_interval_ms] and soon after changes are committed through [Self::run]. Dropping the handle stops the thread and drops the repository without a last commit and push (use [Self::stop] to get the repository back instead). */
+pub struct SyncHandle {
+    sender: Sender<Command>,
+    state: Arc<Mutex<SyncState>>,
+    changes: Receiver<SyncState>,
+    /// the engine's own token, a child of the repository's
+    cancel: CancelToken,
+    /// the repository's token, given back with it
+    parent_cancel: CancelToken,
+    thread: Option<JoinHandle<CodexRepository>>,
+}
+impl SyncHandle {
+    /** `state` is what the engine is doing now */
+    pub fn state(&self) -> SyncState {
+        self.state
+            .lock()
+            .map(|s| s.clone())
+            .unwrap_or(SyncState::Idle)
+    }
+    /** `changes` receives each new state as the engine moves to it */
+    pub fn changes(&self) -> &Receiver<SyncState> {
+        &self.changes
+    }
+    /** `sync_now` asks for a sync without waiting for the interval (or the offline backoff) */
+    pub fn sync_now(&self) -> NullResult {
+        self.send(Command::SyncNow)
+    }
+    /** `run` has the sync thread call `op` with the repository, such as to write files and [CodexRepository::commit] them; committed changes are then pushed after [SyncOptions::push_debounce_ms]. If `op` panics, the panic is logged and the engine carries on. */
+    pub fn run(&self, op: impl FnOnce(&mut CodexRepository) + Send + 'static) -> NullResult {
+        self.send(Command::Run(Box::new(op)))
+    }
+    /** `stop` stops syncing, cancelling any network operation in progress, and gives the repository back */
+    pub fn stop(mut self) -> Result<CodexRepository> {
+        self.finish()?
+            .ok_or_else(|| anyhow!("sync engine already stopped").into())
+    }
+    fn send(&self, command: Command) -> NullResult {
+        self.sender
+            .send(command)
+            .map_err(|_| anyhow!("sync engine has stopped").into())
+    }
+    /** `finish` stops the thread */
+    fn finish(&mut self) -> Result<Option<CodexRepository>> {
+        let thread = match self.thread.take() {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let _ = self.sender.send(Command::Stop);
+        self.cancel.cancel();
+        let mut repo = thread.join().map_err(|_| anyhow!("sync thread panicked"))?;
+        repo.config.cancel = self.parent_cancel.clone();
+        Ok(Some(repo))
+    }
+}
+impl Drop for SyncHandle {
+    fn drop(&mut self) {
+        match self.finish() {
+            Ok(Some(repo)) => repo.drop_without_sync(),
+            Ok(None) => {}
+            Err(e) => error!("stopping sync engine: {}", e),
+        }
+    }
+}
+
+/** `Engine` is the state of the sync thread */
+struct Engine {
+    repo: CodexRepository,
+    options: SyncOptions,
+    state: Arc<Mutex<SyncState>>,
+    changes: Sender<SyncState>,
+    /// when to sync next
+    next_sync: Instant,
+    /// number of syncs in a row that found the remote unreachable
+    offline_attempts: u32,
+    /// when the first change not yet pushed was made
+    pending_since: Option<Instant>,
+}
+impl Engine {
+    /** `work` runs commands and syncs until told to stop */
+    fn work(mut self, commands: Receiver<Command>) -> CodexRepository {
+        loop {
+            let wait = self.next_sync.saturating_duration_since(Instant::now());
+            match commands.recv_timeout(wait) {
+                Ok(Command::SyncNow) | Err(RecvTimeoutError::Timeout) => self.sync(),
+                Ok(Command::Run(op)) => {
+                    let repo = &mut self.repo;
+                    if catch_unwind(AssertUnwindSafe(|| op(repo))).is_err() {
+                        error!("sync engine operation panicked");
+                    }
+                    // while offline, the backoff decides
+                    if (self.repo.needs_commit || self.repo.needs_push)
+                        && self.offline_attempts == 0
+                    {
+                        self.debounce();
+                    }
+                }
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    sync_trace!("sync engine stopped");
+                    return self.repo;
+                }
+            }
+        }
+    }
+    /** `debounce` puts the next sync off until [SyncOptions::push_debounce_ms] from now, but no later than [SyncOptions::push_max_delay_ms] after the first unpushed change */
+    fn debounce(&mut self) {
+        let now = Instant::now();
+        let first = *self.pending_since.get_or_insert(now);
+        let mut next = now + Duration::from_millis(self.options.push_debounce_ms);
+        if let Some(max_delay) = self.options.push_max_delay_ms {
+            next = next.min(first + Duration::from_millis(max_delay));
+        }
+        self.next_sync = next;
+    }
+    /** `sync` commits, fetches and pushes, and works out when to sync next */
+    fn sync(&mut self) {
+        self.set_state(SyncState::Syncing);
+        self.pending_since = None;
+        let result = self
+            .repo
+            .commit()
+            .and_then(|_| self.repo.fetch())
+            .and_then(|_| self.repo.push(false));
+        let interval = Duration::from_millis(self.options.fetch_interval_ms);
+        match result {
+            Ok(()) => {
+                self.offline_attempts = 0;
+                self.next_sync = Instant::now() + interval;
+                self.set_state(SyncState::Idle);
+            }
+            Err(e) if e.is_retryable() || matches!(e, CodexGitError::TimedOut { .. }) => {
+                self.offline_attempts += 1;
+                let delay = self.options.offline_backoff.delay(self.offline_attempts);
+                sync_trace!("offline ({}), trying again in {:?}", e, delay);
+                self.next_sync = Instant::now() + delay;
+                self.set_state(SyncState::Offline);
+            }
+            Err(CodexGitError::MergeConflict { paths }) => {
+                self.offline_attempts = 0;
+                self.next_sync = Instant::now() + interval;
+                self.set_state(SyncState::Conflict { paths });
+            }
+            Err(e) => {
+                error!("sync failed: {}", e);
+                self.offline_attempts = 0;
+                self.next_sync = Instant::now() + interval;
+                self.set_state(SyncState::Idle);
+            }
+        }
+    }
+    fn set_state(&self, state: SyncState) {
+        if let Ok(mut current) = self.state.lock() {
+            *current = state.clone();
+        }
+        let _ = self.changes.send(state);
+    }
+}
+
+impl CodexRepository {
+    /** `sync_in_background` moves the repository onto a background thread that keeps it in sync with the remote, starting with a sync straight away. Use [SyncHandle::stop] to get it back. Cancelling the repository's [CancelToken] stops the engine's network operations as well; stopping the engine does not cancel (or reset) that token. */
+    pub fn sync_in_background(mut self, options: SyncOptions) -> Result<SyncHandle> {
+        sync_trace!("syncing in background with {:?}", &options);
+        let (sender, commands) = channel();
+        let (changes, changes_receiver) = channel();
+        let state = Arc::new(Mutex::new(SyncState::Idle));
+        let parent_cancel = self.config.cancel.clone();
+        let cancel = parent_cancel.child();
+        self.config.cancel = cancel.clone();
+        let engine = Engine {
+            repo: self,
+            options,
+            state: state.clone(),
+            changes,
+            next_sync: Instant::now(),
+            offline_attempts: 0,
+            pending_since: None,
+        };
+        let thread = std::thread::Builder::new()
+            .name("codex-git sync".to_string())
+            .spawn(move || engine.work(commands))?;
+        Ok(SyncHandle {
+            sender,
+            state,
+            changes: changes_receiver,
+            cancel,
+            parent_cancel,
+            thread: Some(thread),
+        })
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
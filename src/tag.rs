@@ -0,0 +1,141 @@
+/*! Tags: named versions of the data, lightweight or annotated. */
+use crate::history::to_datetime;
+use crate::{CodexGitError, CodexRepository, NullResult, Result, User};
+use ansi_term::Colour::*;
+use chrono::{DateTime, FixedOffset};
+use getset::{CopyGetters, Getters};
+use git2::build::CheckoutBuilder;
+use git2::{ObjectType, Oid, Signature};
+use log::trace;
+
+/// tracing macro
+macro_rules! tag_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Red).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** A `TagInfo` describes a tag. The message, tagger and time are only set for annotated tags. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct TagInfo {
+    /// the tag name, such as `v1`
+    #[getset(get = "pub")]
+    name: String,
+    /// the tagged commit, or none if the tag is of a tree or a blob (as some remotes have)
+    #[getset(get_copy = "pub")]
+    target: Option<Oid>,
+    /// whether the tag is annotated (a tag object) rather than lightweight (just a reference)
+    #[getset(get_copy = "pub")]
+    annotated: bool,
+    /// the tag message
+    #[getset(get = "pub")]
+    message: Option<String>,
+    /// who made the tag, as written by [CodexRepository::create_tag] from the config's [User]
+    #[getset(get = "pub")]
+    tagger: Option<User>,
+    /// when the tag was made
+    #[getset(get_copy = "pub")]
+    time: Option<DateTime<FixedOffset>>,
+}
+
+impl CodexRepository {
+    /** `create_tag` tags a revision (such as `HEAD`), as an annotated tag by the configured [User] if there is a message, otherwise as a lightweight tag. It fails if the tag already exists. */
+    pub fn create_tag(&mut self, name: &str, rev: &str, message: Option<&str>) -> Result<Oid> {
+        tag_trace!("tagging {} as {} ({:?})", rev, name, message);
+        let target = self.repo.revparse_single(rev)?.peel(ObjectType::Commit)?;
+        Ok(match message {
+            Some(message) => {
+                let tagger = Signature::now(&self.config.user.name, &self.config.user.email)?;
+                self.repo.tag(name, &target, &tagger, message, false)?
+            }
+            None => self.repo.tag_lightweight(name, &target, false)?,
+        })
+    }
+    /** `tags` lists the tags, in name order, including any that are not of a commit */
+    pub fn tags(&self) -> Result<Vec<TagInfo>> {
+        let mut found = vec![];
+        for name in self.repo.tag_names(None)?.iter().flatten() {
+            let reference = self.repo.find_reference(&format!("refs/tags/{}", name))?;
+            let target = reference.peel_to_commit().ok().map(|c| c.id());
+            let info = match reference
+                .peel(ObjectType::Tag)
+                .ok()
+                .and_then(|t| t.into_tag().ok())
+            {
+                Some(tag) => {
+                    let tagger = tag.tagger();
+                    TagInfo {
+                        name: name.to_string(),
+                        target,
+                        annotated: true,
+                        message: tag.message().map(|m| m.to_string()),
+                        tagger: tagger
+                            .as_ref()
+                            .map(|t| User::new(t.name().unwrap_or(""), t.email().unwrap_or(""))),
+                        time: tagger.as_ref().map(|t| to_datetime(t.when())).transpose()?,
+                    }
+                }
+                None => TagInfo {
+                    name: name.to_string(),
+                    target,
+                    annotated: false,
+                    message: None,
+                    tagger: None,
+                    time: None,
+                },
+            };
+            found.push(info);
+        }
+        Ok(found)
+    }
+    /** `delete_tag` deletes a local tag (not the tag on the remote) */
+    pub fn delete_tag(&mut self, name: &str) -> NullResult {
+        tag_trace!("deleting tag {}", name);
+        self.repo.tag_delete(name)?;
+        Ok(())
+    }
+    /** `checkout_tag` checks out the files as they were at a tag, read-only: `HEAD` is detached, so [CodexRepository::add] and [CodexRepository::commit] fail with [CodexGitError::DetachedHead] until [CodexRepository::switch_branch] goes back to a branch. It fails with [CodexGitError::DirtyWorkingTree] if tracked files have changes that are not committed. */
+    pub fn checkout_tag(&mut self, name: &str) -> NullResult {
+        tag_trace!("checking out tag {}", name);
+        let changed = self.tracked_changes()?;
+        if !changed.is_empty() {
+            return Err(CodexGitError::DirtyWorkingTree { paths: changed });
+        }
+        let commit = self
+            .repo
+            .find_reference(&format!("refs/tags/{}", name))?
+            .peel_to_commit()?;
+        self.repo
+            .checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        self.repo.set_head_detached(commit.id())?;
+        self.needs_push = false;
+        Ok(())
+    }
+    /** `push_tags` pushes some tags to the remote */
+    pub fn push_tags(&mut self, names: &[&str]) -> NullResult {
+        tag_trace!("pushing tags {:?}", names);
+        let refspecs: Vec<String> = names
+            .iter()
+            .map(|name| format!("refs/tags/{}:refs/tags/{}", name, name))
+            .collect();
+        if refspecs.is_empty() {
+            return Ok(());
+        }
+        self.push_refspecs(&refspecs)
+    }
+    /** `push_all_tags` pushes all the local tags to the remote */
+    pub fn push_all_tags(&mut self) -> NullResult {
+        let names: Vec<String> = self
+            .repo
+            .tag_names(None)?
+            .iter()
+            .flatten()
+            .map(|n| n.to_string())
+            .collect();
+        self.push_tags(&names.iter().map(|n| n.as_str()).collect::<Vec<_>>())
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
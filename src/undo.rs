@@ -0,0 +1,165 @@
+/*! Undoing commits: reverting, resetting and discarding local changes. */
+use crate::delete::unpushed_commits;
+use crate::pull::check_conflicts;
+use crate::{CodexGitError, CodexRepository, NullResult, Result, SyncEvent};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use getset::{CopyGetters, Getters};
+use git2::{BranchType, Oid, ResetType};
+use log::trace;
+
+/// tracing macro
+macro_rules! undo_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(Purple).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `ResetMode` is what [CodexRepository::reset_to] keeps, as for `git reset`. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// keep the index and the working tree, so the undone changes are staged
+    Soft,
+    /// keep the working tree but reset the index, so the undone changes are not staged
+    Mixed,
+    /// reset the index and the working tree, losing the changes
+    Hard,
+}
+impl From<ResetMode> for ResetType {
+    fn from(mode: ResetMode) -> Self {
+        match mode {
+            ResetMode::Soft => ResetType::Soft,
+            ResetMode::Mixed => ResetType::Mixed,
+            ResetMode::Hard => ResetType::Hard,
+        }
+    }
+}
+
+/** A `ResetConfirmation` is made by [CodexRepository::prepare_reset_to_remote] to show what [CodexRepository::reset_to_remote] would discard, and is then passed to it to confirm. */
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct ResetConfirmation {
+    /// the local branch
+    #[getset(get = "pub")]
+    branch: String,
+    /// the commit the branch is at
+    #[getset(get_copy = "pub")]
+    local: Oid,
+    /// the commit the branch will be reset to (its upstream, as of the last fetch)
+    #[getset(get_copy = "pub")]
+    remote: Oid,
+    /// the local commits that will be lost, newest first
+    #[getset(get = "pub")]
+    discarded_commits: Vec<Oid>,
+    /// tracked files whose changes will be lost
+    #[getset(get = "pub")]
+    discarded_paths: Vec<String>,
+}
+
+impl CodexRepository {
+    /** `revert` makes a new commit that undoes the changes made by a commit (given as a revision, such as `HEAD~1`). It fails with [CodexGitError::MergeConflict] (leaving the conflicts in the working tree, as a merge does) if later commits changed the same lines. */
+    pub fn revert(&mut self, rev: &str) -> Result<Oid> {
+        undo_trace!("reverting {}", rev);
+        self.check_on_branch()?;
+        let commit = self.repo.revparse_single(rev)?.peel_to_commit()?;
+        self.repo.revert(&commit, None)?;
+        check_conflicts(&self.repo.index()?, &self.events)?;
+        let tree = self.repo.find_tree(self.repo.index()?.write_tree()?)?;
+        let message = format!(
+            "Revert \"{}\"\n\nThis reverts commit {}.",
+            commit.summary().unwrap_or(""),
+            commit.id()
+        );
+        let oid = self.write_commit(tree, &message, &[&self.our_commit()?])?;
+        self.repo.cleanup_state()?;
+        self.events.emit(SyncEvent::Committed {
+            oid: oid.to_string(),
+            message,
+        });
+        self.needs_push = true;
+        Ok(oid)
+    }
+    /** `reset_to` moves the current branch to a revision (such as `HEAD~2`), keeping the index and working tree as the mode says. */
+    pub fn reset_to(&mut self, rev: &str, mode: ResetMode) -> NullResult {
+        undo_trace!("resetting to {} ({:?})", rev, mode);
+        self.check_on_branch()?;
+        let target = self.repo.revparse_single(rev)?.peel_to_commit()?.id();
+        self.reset_commit(target, mode.into())
+    }
+    /** `undo_last_commit` undoes the last commit, keeping its changes staged (as a soft [Self::reset_to]), and is the undone commit. It fails with [CodexGitError::AlreadyPushed] if the commit is on the upstream. */
+    pub fn undo_last_commit(&mut self) -> Result<Oid> {
+        self.check_on_branch()?;
+        let head = self.our_commit()?;
+        let oid = head.id();
+        let parent = head
+            .parent_ids()
+            .next()
+            .ok_or_else(|| anyhow!("commit {} has no parent", oid))?;
+        drop(head);
+        undo_trace!("undoing commit {}", oid);
+        if let Some(upstream) = self.upstream_commit(&self.current_branch()?) {
+            if upstream == oid || self.repo.graph_descendant_of(upstream, oid)? {
+                return Err(CodexGitError::AlreadyPushed(oid.to_string()));
+            }
+        }
+        self.reset_commit(parent, ResetType::Soft)?;
+        Ok(oid)
+    }
+    /** `prepare_reset_to_remote` lists what [Self::reset_to_remote] would discard from the current branch */
+    pub fn prepare_reset_to_remote(&self) -> Result<ResetConfirmation> {
+        let branch = self.current_branch()?;
+        let remote = self
+            .upstream_commit(&branch)
+            .ok_or_else(|| anyhow!("branch {} has no upstream", branch))?;
+        let local = self.our_commit()?.id();
+        let mut walk = self.repo.revwalk()?;
+        walk.push(local)?;
+        walk.hide(remote)?;
+        let discarded_commits = walk.collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(ResetConfirmation {
+            branch,
+            local,
+            remote,
+            discarded_commits,
+            discarded_paths: self.tracked_changes()?,
+        })
+    }
+    /** `reset_to_remote` discards the local commits and the changes to tracked files on the current branch, resetting it to its upstream (as of the last fetch). The confirmation must come from [Self::prepare_reset_to_remote]; if the branch or upstream have moved since, or other files have been changed, it fails with [CodexGitError::StaleConfirmation]. */
+    pub fn reset_to_remote(&mut self, confirmation: &ResetConfirmation) -> NullResult {
+        undo_trace!("resetting to remote, discarding {:?}", confirmation);
+        let current = self.prepare_reset_to_remote()?;
+        if current != *confirmation {
+            return Err(CodexGitError::StaleConfirmation);
+        }
+        self.reset_commit(confirmation.remote, ResetType::Hard)
+    }
+    /** `upstream_commit` is the commit at the upstream of a local branch */
+    fn upstream_commit(&self, branch: &str) -> Option<Oid> {
+        self.repo
+            .find_branch(branch, BranchType::Local)
+            .ok()?
+            .upstream()
+            .ok()?
+            .get()
+            .target()
+    }
+    /** `reset_commit` resets the current branch to a commit, then works out again whether there is anything to commit or push */
+    pub(crate) fn reset_commit(&mut self, oid: Oid, kind: ResetType) -> NullResult {
+        {
+            let commit = self.repo.find_commit(oid)?;
+            self.repo.reset(commit.as_object(), kind, None)?;
+        }
+        self.added.clear();
+        self.needs_commit = !self.tracked_changes()?.is_empty();
+        self.needs_push = unpushed_commits(&self.repo)? > 0;
+        undo_trace!(
+            "after reset, needs commit {}, needs push {}",
+            self.needs_commit,
+            self.needs_push
+        );
+        Ok(())
+    }
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
@@ -0,0 +1,189 @@
+/*! Watching the working tree and committing changes as they are made, so the repository behaves like a synced folder. */
+use crate::auto_add::Matcher;
+use crate::{CodexRepository, Result};
+use ansi_term::Colour::*;
+use anyhow::anyhow;
+use getset::{CopyGetters, Setters};
+use log::{error, trace};
+use notify::{recommended_watcher, Event, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// tracing macro
+macro_rules! watch_trace {
+    () => {  };
+    ($($arg:tt)*) => {
+        trace!("{} ({}:{})", Black.on(White).paint(format!($($arg)*)), std::file!(), std::line!());
+    };
+}
+
+/** `WatchOptions` controls a [WatchHandle]. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters, Setters, Serialize, Deserialize)]
+#[getset(get_copy = "pub", set = "pub")]
+#[serde(default)]
+pub struct WatchOptions {
+    /// how long (in milliseconds) the files must be left unchanged before the changes are committed
+    quiet_period_ms: u64,
+}
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            quiet_period_ms: 2000,
+        }
+    }
+}
+
+/** `Message` is what the watcher thread is told */
+enum Message {
+    /// files changed
+    Changed(notify::Result<Event>),
+    /// commit anything outstanding and stop
+    Stop,
+}
+
+/** A `WatchHandle` is a [CodexRepository] being watched on a background thread, made by [CodexRepository::watch]. Changes to files selected by the [crate::AutoAdd] rules (including deletions) are committed (not pushed) once the files have been left alone for the quiet period. Dropping the handle stops the watcher, committing any outstanding changes, and then drops the repository without pushing. */
+pub struct WatchHandle {
+    sender: std::sync::mpsc::Sender<Message>,
+    thread: Option<JoinHandle<CodexRepository>>,
+}
+impl WatchHandle {
+    /** `stop` commits any outstanding changes, stops watching and gives the repository back */
+    pub fn stop(mut self) -> Result<CodexRepository> {
+        self.finish()?
+            .ok_or_else(|| anyhow!("watcher already stopped").into())
+    }
+    /** `finish` stops the thread */
+    fn finish(&mut self) -> Result<Option<CodexRepository>> {
+        let thread = match self.thread.take() {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        // the thread may have stopped already, and then the join says why
+        let _ = self.sender.send(Message::Stop);
+        Ok(Some(
+            thread
+                .join()
+                .map_err(|_| anyhow!("watcher thread panicked"))?,
+        ))
+    }
+}
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        match self.finish() {
+            Ok(Some(repo)) => repo.drop_without_sync(),
+            Ok(None) => {}
+            Err(e) => error!("stopping watcher: {}", e),
+        }
+    }
+}
+
+impl CodexRepository {
+    /** `watch` moves the repository onto a background thread that commits changes as files are written. Use [WatchHandle::stop] to get it back. It fails if the [crate::AutoAdd] rules are not valid pathspecs. */
+    pub fn watch(self, options: WatchOptions) -> Result<WatchHandle> {
+        let workdir = self
+            .repo
+            .workdir()
+            .ok_or_else(|| anyhow!("repository has no working tree"))?;
+        let workdir = workdir.canonicalize()?;
+        // the matcher cannot be sent to the thread, so check the rules here and make it there
+        Matcher::new(&self.config.auto_add)?;
+        watch_trace!("watching {:?} with {:?}", &workdir, options);
+        let (sender, receiver) = channel();
+        let changes = sender.clone();
+        let mut watcher = recommended_watcher(move |event| {
+            let _ = changes.send(Message::Changed(event));
+        })
+        .map_err(|e| anyhow!("cannot watch files: {}", e))?;
+        watcher
+            .watch(&workdir, RecursiveMode::Recursive)
+            .map_err(|e| anyhow!("cannot watch {:?}: {}", &workdir, e))?;
+        let thread = std::thread::Builder::new()
+            .name("codex-git watcher".to_string())
+            .spawn(move || {
+                let mut repo = self;
+                // stops watching when the thread ends
+                let _watcher = watcher;
+                match Matcher::new(&repo.config.auto_add) {
+                    Ok(matcher) => repo.watch_loop(&workdir, &matcher, options, receiver),
+                    Err(e) => error!("bad auto add rules: {}", e),
+                }
+                repo
+            })?;
+        Ok(WatchHandle {
+            sender,
+            thread: Some(thread),
+        })
+    }
+    /** `watch_loop` collects changes until they have been quiet for long enough, then commits them */
+    fn watch_loop(
+        &mut self,
+        workdir: &Path,
+        matcher: &Matcher,
+        options: WatchOptions,
+        receiver: Receiver<Message>,
+    ) {
+        let quiet = Duration::from_millis(options.quiet_period_ms);
+        let mut changed_at: Option<Instant> = None;
+        loop {
+            let message = match changed_at {
+                Some(at) => receiver.recv_timeout(quiet.saturating_sub(at.elapsed())),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match message {
+                Ok(Message::Changed(Ok(event))) => {
+                    if is_watched(workdir, matcher, &event.paths) {
+                        watch_trace!("changed {:?}", &event.paths);
+                        changed_at = Some(Instant::now());
+                    }
+                }
+                Ok(Message::Changed(Err(e))) => error!("watcher error: {}", e),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.commit_watched();
+                    changed_at = None;
+                }
+                Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    if changed_at.is_some() {
+                        self.commit_watched();
+                    }
+                    watch_trace!("stopped watching");
+                    return;
+                }
+            }
+        }
+    }
+    /** `commit_watched` stages and commits the changes, as [CodexRepository::commit] */
+    fn commit_watched(&mut self) {
+        match self.auto_add_dry_run() {
+            Ok(found) if found.is_empty() => return,
+            Ok(_) => {}
+            Err(e) => {
+                error!("watcher cannot list changes: {}", e);
+                return;
+            }
+        }
+        let needed = self.needs_commit;
+        self.needs_commit = true;
+        if let Err(e) = self.commit() {
+            error!("watcher cannot commit: {}", e);
+            self.needs_commit = needed;
+        }
+    }
+}
+/** `is_watched` is whether any of the paths are selected by the [crate::AutoAdd] rules (and not in `.git`) */
+fn is_watched(workdir: &Path, matcher: &Matcher, paths: &[PathBuf]) -> bool {
+    paths.iter().any(|path| match path.strip_prefix(workdir) {
+        Ok(relative) => {
+            !relative
+                .components()
+                .any(|c| c == Component::Normal(".git".as_ref()))
+                && matcher.matches(relative)
+        }
+        Err(_) => false,
+    })
+}
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */